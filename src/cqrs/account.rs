@@ -1,24 +1,621 @@
-// struct Deltas {
+/**
+Implementation of the Account type events.
 
-// }
+An Account is owned by an AccountHolder and goes through the lifecycle
+open -> (freeze <-> unfreeze) -> close. A closed account can not be changed any more.
 
+An account holds money in a single currency, chosen when it is opened. Money is moved with
+deposits and withdrawals, which must be positive amounts in the account currency.
+A withdrawal is rejected if it would take the balance below zero, or below minus the overdraft limit
+if one has been set. Only open accounts can be withdrawn from, frozen accounts still accept deposits.
+Transfers between accounts move money with `debit` and `credit`, see `cqrs::transaction`.
+The bank charges fees and pays interest with `charge_fee` and `pay_interest`, which open and frozen accounts accept.
+An account can only be closed when its balance is zero.
 
+Every command rebuilds the current state of the account from its newest snapshot and the events after it,
+see `cqrs::snapshot`, checks that the command is allowed in that state, and appends the resulting event
+to the event store.
+
+# Example:
+
+```
+    let store = RqlEventStore::open_default()?;
+
+    let opened = open_account(&store, &account_holder_id, Currency::SEK)?;
+    let frozen = freeze_account(&store, &opened.aggregate_id)?;
+    let unfrozen = unfreeze_account(&store, &opened.aggregate_id)?;
+
+    deposit(&store, &opened.aggregate_id, "100.00 SEK".parse()?)?;
+    withdraw(&store, &opened.aggregate_id, Money::new(2550, Currency::SEK))?;
+    set_overdraft_limit(&store, &opened.aggregate_id, "500 SEK".parse()?)?;
+    charge_fee(&store, &opened.aggregate_id, "25 SEK".parse()?)?;
+    pay_interest(&store, &opened.aggregate_id, "0.75 SEK".parse()?)?;
+
+    let closed = close_account(&store, &opened.aggregate_id)?;
+```
+*/
+
+use std::collections::HashMap;
+use rql::prelude::*;
+use thiserror::Error;
+use crate::cqrs::domain_event::DomainEvent;
+use crate::cqrs::event::*;
+use crate::cqrs::money::{Currency, Money, MoneyError};
+use crate::cqrs::snapshot::rehydrate;
+use crate::database::event_store::{AppendError, EventStore};
+use crate::projections::account::{Account, AccountStatus};
+use crate::projections::account_holder::AccountHolder;
+use crate::projections::balance::balance_change;
+
+#[allow(dead_code)]
+static AGGREGATE_TYPE: &str = "Account";
+
+/// Everything that can happen to an Account, stored as `event_name` and `deltas` of the `Event`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event_name", rename_all = "snake_case")]
+pub enum AccountEvent {
+    New {
+        account_holder_id: String,
+        /// Accounts opened before there were currencies are in SEK.
+        #[serde(default)]
+        currency: Currency,
+    },
+    FreezeAccount,
+    UnfreezeAccount,
+    CloseAccount,
+    Deposit {
+        amount: Money,
+    },
+    Withdraw {
+        amount: Money,
+    },
+    SetOverdraftLimit {
+        overdraft_limit: Money,
+    },
+    ChargeFee {
+        amount: Money,
+    },
+    PayInterest {
+        amount: Money,
+    },
+    Debit {
+        amount: Money,
+        transfer_id: String,
+    },
+    Credit {
+        amount: Money,
+        transfer_id: String,
+    },
+    ReverseDebit {
+        amount: Money,
+        transfer_id: String,
+        reason: String,
+    },
+}
+
+impl DomainEvent for AccountEvent {
+    const AGGREGATE_TYPE: &'static str = "Account";
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AccountError {
+    #[error("account holder {0} not found")]
+    AccountHolderNotFound(String),
+    #[error("account {0} not found")]
+    AccountNotFound(String),
+    /// The command is not allowed in the current status of the account, e.g. unfreezing an open account.
+    #[error("can not {command} account {aggregate_id} with status {status:?}")]
+    InvalidStatus {
+        aggregate_id: String,
+        status: AccountStatus,
+        command: &'static str,
+    },
+    /// Amounts must be positive, and overdraft limits can not be negative.
+    #[error("invalid amount {0}")]
+    InvalidAmount(Money),
+    /// The withdrawal would take the balance below the allowed overdraft.
+    #[error("can not withdraw {amount} from account {aggregate_id} with balance {balance} and overdraft limit {overdraft_limit}")]
+    InsufficientFunds {
+        aggregate_id: String,
+        balance: Money,
+        overdraft_limit: Money,
+        amount: Money,
+    },
+    /// Only accounts with a zero balance can be closed.
+    #[error("can not close account {aggregate_id} with balance {balance}")]
+    NonZeroBalance {
+        aggregate_id: String,
+        balance: Money,
+    },
+    /// E.g. an amount in another currency than the account, or a balance out of range.
+    #[error("{0}")]
+    Money(#[from] MoneyError),
+    #[error("{0}")]
+    Append(#[from] AppendError),
+}
+
+/// Opens a new account in the given currency for an existing, not deleted, AccountHolder.
+#[allow(dead_code)]
+pub fn open_account(store: &dyn EventStore, account_holder_id: &str, currency: Currency) -> Result<Event, AccountError> {
+    let account_holder_events = store.read_stream(account_holder_id, "AccountHolder");
+    match AccountHolder::from_events(&account_holder_events) {
+        Some(account_holder) if !account_holder.deleted => {},
+        _ => return Err(AccountError::AccountHolderNotFound(account_holder_id.into())),
+    }
+
+    let domain_event = AccountEvent::New {
+        account_holder_id: account_holder_id.into(),
+        currency,
+    };
+    let event = Event::from_domain_event(&domain_event, HashMap::new());
+
+    store.append(event.clone(), 0)?;
+
+    Ok(event)
+}
+
+#[allow(dead_code)]
+pub fn freeze_account(store: &dyn EventStore, aggregate_id: &str) -> Result<Event, AccountError> {
+    change_status(store, aggregate_id, "freeze_account", AccountEvent::FreezeAccount, &[AccountStatus::Open])
+}
+
+#[allow(dead_code)]
+pub fn unfreeze_account(store: &dyn EventStore, aggregate_id: &str) -> Result<Event, AccountError> {
+    change_status(store, aggregate_id, "unfreeze_account", AccountEvent::UnfreezeAccount, &[AccountStatus::Frozen])
+}
+
+#[allow(dead_code)]
+pub fn close_account(store: &dyn EventStore, aggregate_id: &str) -> Result<Event, AccountError> {
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_status(&account, "close_account", &[AccountStatus::Open, AccountStatus::Frozen])?;
+
+    if !account.balance.is_zero() {
+        return Err(AccountError::NonZeroBalance {
+            aggregate_id: aggregate_id.into(),
+            balance: account.balance,
+        })
+    }
+
+    append(store, &latest_event, AccountEvent::CloseAccount)
+}
+
+/// Deposits `amount` on the account.
+#[allow(dead_code)]
+pub fn deposit(store: &dyn EventStore, aggregate_id: &str, amount: Money) -> Result<Event, AccountError> {
+    change_balance(store, aggregate_id, "deposit", amount, AccountEvent::Deposit { amount })
+}
+
+/// Withdraws `amount` from the account.
+///
+/// The account is rebuilt from its full event stream before the balance is checked, and the append
+/// is rejected if another event was stored in the meantime, so two withdrawals can never both pass
+/// the check against the same balance.
+#[allow(dead_code)]
+pub fn withdraw(store: &dyn EventStore, aggregate_id: &str, amount: Money) -> Result<Event, AccountError> {
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_withdrawal(&account, "withdraw", amount)?;
+
+    append(store, &latest_event, AccountEvent::Withdraw { amount })
+}
+
+/// Allows the balance to go down to minus `overdraft_limit`. Zero means no overdraft.
+#[allow(dead_code)]
+pub fn set_overdraft_limit(store: &dyn EventStore, aggregate_id: &str, overdraft_limit: Money) -> Result<Event, AccountError> {
+    if overdraft_limit.is_negative() {
+        return Err(AccountError::InvalidAmount(overdraft_limit))
+    }
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_status(&account, "set_overdraft_limit", &[AccountStatus::Open, AccountStatus::Frozen])?;
+    account.balance.checked_add(overdraft_limit)?;
+
+    append(store, &latest_event, AccountEvent::SetOverdraftLimit { overdraft_limit })
+}
+
+/// Charges the account a fee of `amount`, booked as income of the bank.
+/// Fees are charged even if they take the balance beyond the overdraft limit.
+#[allow(dead_code)]
+pub fn charge_fee(store: &dyn EventStore, aggregate_id: &str, amount: Money) -> Result<Event, AccountError> {
+    change_balance(store, aggregate_id, "charge_fee", amount, AccountEvent::ChargeFee { amount })
+}
+
+/// Pays `amount` of interest to the account, booked as an expense of the bank.
+#[allow(dead_code)]
+pub fn pay_interest(store: &dyn EventStore, aggregate_id: &str, amount: Money) -> Result<Event, AccountError> {
+    change_balance(store, aggregate_id, "pay_interest", amount, AccountEvent::PayInterest { amount })
+}
+
+/// Takes `amount` from the account on behalf of the transfer `transfer_id`. Same rules as a withdrawal.
+pub fn debit(store: &dyn EventStore, aggregate_id: &str, amount: Money, transfer_id: &str) -> Result<Event, AccountError> {
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_withdrawal(&account, "debit", amount)?;
+
+    append(store, &latest_event, AccountEvent::Debit { amount, transfer_id: transfer_id.into() })
+}
+
+/// Puts `amount` on the account on behalf of the transfer `transfer_id`. Same rules as a deposit.
+pub fn credit(store: &dyn EventStore, aggregate_id: &str, amount: Money, transfer_id: &str) -> Result<Event, AccountError> {
+    change_balance(store, aggregate_id, "credit", amount, AccountEvent::Credit { amount, transfer_id: transfer_id.into() })
+}
+
+/// Gives back the money taken by `debit` when the transfer `transfer_id` could not be completed, and why.
+/// The money has already left the account, so this is accepted whatever the status of the account is.
+pub fn reverse_debit(store: &dyn EventStore, aggregate_id: &str, amount: Money, transfer_id: &str, reason: &str) -> Result<Event, AccountError> {
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    account.balance.checked_add(amount)?;
+
+    let domain_event = AccountEvent::ReverseDebit {
+        amount,
+        transfer_id: transfer_id.into(),
+        reason: reason.into(),
+    };
+
+    append(store, &latest_event, domain_event)
+}
+
+/// Rebuilds the account from its event stream.
+pub fn load_account(store: &dyn EventStore, aggregate_id: &str) -> Result<(Account, Event), AccountError> {
+    rehydrate::<Account>(store, aggregate_id).ok_or_else(|| AccountError::AccountNotFound(aggregate_id.into()))
+}
+
+/// Rebuilds every account opened for the AccountHolder. Accounts are found by scanning the event log for their
+/// `new` events, so this is only meant for rare commands, like deleting the AccountHolder.
+pub fn load_accounts_of_account_holder(store: &dyn EventStore, account_holder_id: &str) -> Vec<Account> {
+    store.read_all(1)
+        .iter()
+        .filter(|event| event.aggregate_type == AGGREGATE_TYPE)
+        .filter(|event| matches!(
+            event.decode::<AccountEvent>(),
+            Ok(AccountEvent::New { account_holder_id: owner, .. }) if owner == account_holder_id
+        ))
+        .filter_map(|event| load_account(store, &event.aggregate_id).ok())
+        .map(|(account, _)| account)
+        .collect()
+}
+
+fn change_status(
+        store: &dyn EventStore,
+        aggregate_id: &str,
+        command: &'static str,
+        domain_event: AccountEvent,
+        allowed_statuses: &[AccountStatus],
+        ) -> Result<Event, AccountError> {
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_status(&account, command, allowed_statuses)?;
+
+    append(store, &latest_event, domain_event)
+}
+
+/// Moves a positive amount of money onto or, for fees, off an open or frozen account, without checking the funds.
+fn change_balance(
+        store: &dyn EventStore,
+        aggregate_id: &str,
+        command: &'static str,
+        amount: Money,
+        domain_event: AccountEvent,
+        ) -> Result<Event, AccountError> {
+    if !amount.is_positive() {
+        return Err(AccountError::InvalidAmount(amount))
+    }
+    let (account, latest_event) = load_account(store, aggregate_id)?;
+    check_status(&account, command, &[AccountStatus::Open, AccountStatus::Frozen])?;
+    let change = balance_change(&domain_event).expect("only events that move money change the balance");
+    account.balance.checked_add(change)?;
+
+    append(store, &latest_event, domain_event)
+}
+
+/// Checks that `amount` may be taken from the account: it must be open, and the balance
+/// may not go below minus the overdraft limit.
+fn check_withdrawal(account: &Account, command: &'static str, amount: Money) -> Result<(), AccountError> {
+    if !amount.is_positive() {
+        return Err(AccountError::InvalidAmount(amount))
+    }
+    check_status(account, command, &[AccountStatus::Open])?;
+
+    let balance_after = account.balance.checked_sub(amount)?;
+    if balance_after.checked_add(account.overdraft_limit)?.is_negative() {
+        return Err(AccountError::InsufficientFunds {
+            aggregate_id: account.aggregate_id.clone(),
+            balance: account.balance,
+            overdraft_limit: account.overdraft_limit,
+            amount,
+        })
+    }
+
+    Ok(())
+}
+
+fn check_status(account: &Account, command: &'static str, allowed_statuses: &[AccountStatus]) -> Result<(), AccountError> {
+    if !allowed_statuses.contains(&account.status) {
+        return Err(AccountError::InvalidStatus {
+            aggregate_id: account.aggregate_id.clone(),
+            status: account.status,
+            command,
+        })
+    }
+
+    Ok(())
+}
+
+/// Appends the domain event to the stream, on top of `latest_event`.
+fn append(store: &dyn EventStore, latest_event: &Event, domain_event: AccountEvent) -> Result<Event, AccountError> {
+    let event = latest_event.update_with(&domain_event, HashMap::new());
+
+    store.append(event.clone(), latest_event.aggregate_version)?;
+
+    Ok(event)
+}
+
+// cargo test -- --nocapture
 #[cfg(test)]
     mod tests {
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
         use super::*;
 
         #[test]
-        fn internal() {
-            assert_eq!(4, internal_adder(2, 2));
+        fn opens_account_for_account_holder() {
+            let store = InMemoryEventStore::new();
+            let account_holder_id = store_new_account_holder(&store);
+
+            let event = open_account(&store, &account_holder_id, Currency::SEK).unwrap();
+
+            assert_eq!(event.event_name, "new");
+            assert_eq!(event.aggregate_type, "Account");
+            assert_eq!(event.aggregate_version, 1);
+            assert_eq!(event.deltas.get("account_holder_id"), Some(&account_holder_id));
+            assert_eq!(event.deltas.get("currency").unwrap(), "SEK");
+            assert_eq!(store.read_stream(&event.aggregate_id, "Account").len(), 1);
         }
 
-        // fn generates_events_for_new_account(){
-        //     let expected = Event{
-        //         deltas: {,
-        //             social security number, 
-        //             date of birth, 
-        //             phone number, 
-        //             home address}
-        //         } 
-        // }
-    }
\ No newline at end of file
+        #[test]
+        fn can_not_open_account_for_unknown_account_holder() {
+            let store = InMemoryEventStore::new();
+
+            let result = open_account(&store, "unknown", Currency::SEK);
+
+            assert_eq!(result, Err(AccountError::AccountHolderNotFound("unknown".into())));
+        }
+
+        #[test]
+        fn can_not_open_account_for_deleted_account_holder() {
+            let store = InMemoryEventStore::new();
+            let account_holder_id = store_new_account_holder(&store);
+            let new_event = store.last_event(&account_holder_id, "AccountHolder").unwrap();
+            let delete_event = new_event.update(
+                HashMap::from([("reason".into(), "requested by the account holder".into())]),
+                HashMap::new(),
+                "delete_account_holder",
+            );
+            store.append(delete_event, 1).unwrap();
+
+            let result = open_account(&store, &account_holder_id, Currency::SEK);
+
+            assert_eq!(result, Err(AccountError::AccountHolderNotFound(account_holder_id)));
+        }
+
+        #[test]
+        fn freezes_unfreezes_and_closes_account() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+
+            let frozen = freeze_account(&store, &account_id).unwrap();
+            let unfrozen = unfreeze_account(&store, &account_id).unwrap();
+            let closed = close_account(&store, &account_id).unwrap();
+
+            assert_eq!(frozen.event_name, "freeze_account");
+            assert_eq!(frozen.aggregate_version, 2);
+            assert_eq!(unfrozen.event_name, "unfreeze_account");
+            assert_eq!(unfrozen.aggregate_version, 3);
+            assert_eq!(closed.event_name, "close_account");
+            assert_eq!(closed.aggregate_version, 4);
+            assert_eq!(load_account(&store, &account_id).unwrap().0.status, AccountStatus::Closed);
+        }
+
+        #[test]
+        fn can_close_frozen_account() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            freeze_account(&store, &account_id).unwrap();
+
+            assert!(close_account(&store, &account_id).is_ok());
+        }
+
+        #[test]
+        fn rejects_invalid_status_transitions() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+
+            let unfreeze_open = unfreeze_account(&store, &account_id);
+            freeze_account(&store, &account_id).unwrap();
+            let freeze_frozen = freeze_account(&store, &account_id);
+            close_account(&store, &account_id).unwrap();
+            let unfreeze_closed = unfreeze_account(&store, &account_id);
+            let close_closed = close_account(&store, &account_id);
+
+            assert_eq!(unfreeze_open, Err(AccountError::InvalidStatus {
+                aggregate_id: account_id.clone(),
+                status: AccountStatus::Open,
+                command: "unfreeze_account",
+            }));
+            assert_eq!(freeze_frozen, Err(AccountError::InvalidStatus {
+                aggregate_id: account_id.clone(),
+                status: AccountStatus::Frozen,
+                command: "freeze_account",
+            }));
+            assert!(matches!(unfreeze_closed, Err(AccountError::InvalidStatus { status: AccountStatus::Closed, .. })));
+            assert!(matches!(close_closed, Err(AccountError::InvalidStatus { status: AccountStatus::Closed, .. })));
+            assert_eq!(store.latest_version(&account_id, "Account"), 3);
+        }
+
+        #[test]
+        fn deposits_and_withdraws() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+
+            let deposit_event = deposit(&store, &account_id, sek(10000)).unwrap();
+            let withdraw_event = withdraw(&store, &account_id, sek(2550)).unwrap();
+
+            assert_eq!(deposit_event.event_name, "deposit");
+            assert_eq!(deposit_event.deltas.get("amount").unwrap(), "100.00 SEK");
+            assert_eq!(withdraw_event.event_name, "withdraw");
+            assert_eq!(withdraw_event.aggregate_version, 3);
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(7450));
+        }
+
+        #[test]
+        fn rejects_withdrawal_below_zero() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            deposit(&store, &account_id, sek(10000)).unwrap();
+
+            let result = withdraw(&store, &account_id, sek(10001));
+
+            assert_eq!(result, Err(AccountError::InsufficientFunds {
+                aggregate_id: account_id.clone(),
+                balance: sek(10000),
+                overdraft_limit: sek(0),
+                amount: sek(10001),
+            }));
+            assert!(withdraw(&store, &account_id, sek(10000)).is_ok());
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(0));
+        }
+
+        #[test]
+        fn allows_withdrawal_within_overdraft_limit() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            deposit(&store, &account_id, sek(10000)).unwrap();
+            set_overdraft_limit(&store, &account_id, sek(5000)).unwrap();
+
+            let within_limit = withdraw(&store, &account_id, sek(15000));
+            let beyond_limit = withdraw(&store, &account_id, sek(1));
+
+            assert!(within_limit.is_ok());
+            assert!(matches!(beyond_limit, Err(AccountError::InsufficientFunds { .. })));
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(-5000));
+        }
+
+        #[test]
+        fn rejects_non_positive_amounts() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+
+            assert_eq!(deposit(&store, &account_id, sek(0)), Err(AccountError::InvalidAmount(sek(0))));
+            assert_eq!(withdraw(&store, &account_id, sek(-100)), Err(AccountError::InvalidAmount(sek(-100))));
+            assert_eq!(set_overdraft_limit(&store, &account_id, sek(-1)), Err(AccountError::InvalidAmount(sek(-1))));
+        }
+
+        #[test]
+        fn rejects_amounts_in_other_currency() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+
+            let result = deposit(&store, &account_id, Money::new(10000, Currency::EUR));
+
+            assert_eq!(result, Err(AccountError::Money(MoneyError::CurrencyMismatch(Currency::SEK, Currency::EUR))));
+            assert_eq!(store.latest_version(&account_id, "Account"), 1);
+        }
+
+        #[test]
+        fn frozen_account_accepts_deposits_but_not_withdrawals() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            freeze_account(&store, &account_id).unwrap();
+
+            assert!(deposit(&store, &account_id, sek(10000)).is_ok());
+            assert!(matches!(withdraw(&store, &account_id, sek(100)), Err(AccountError::InvalidStatus { status: AccountStatus::Frozen, .. })));
+        }
+
+        #[test]
+        fn can_not_close_account_with_money_on_it() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            deposit(&store, &account_id, sek(10000)).unwrap();
+
+            let result = close_account(&store, &account_id);
+
+            assert_eq!(result, Err(AccountError::NonZeroBalance { aggregate_id: account_id.clone(), balance: sek(10000) }));
+            withdraw(&store, &account_id, sek(10000)).unwrap();
+            assert!(close_account(&store, &account_id).is_ok());
+        }
+
+        #[test]
+        fn debits_and_credits_on_behalf_of_transfer() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            deposit(&store, &account_id, sek(10000)).unwrap();
+
+            let debit_event = debit(&store, &account_id, sek(3000), "transfer-1").unwrap();
+            let beyond_balance = debit(&store, &account_id, sek(7001), "transfer-2");
+            freeze_account(&store, &account_id).unwrap();
+            let credit_event = credit(&store, &account_id, sek(500), "transfer-3").unwrap();
+
+            assert_eq!(debit_event.event_name, "debit");
+            assert_eq!(debit_event.deltas.get("transfer_id").unwrap(), "transfer-1");
+            assert!(matches!(beyond_balance, Err(AccountError::InsufficientFunds { .. })));
+            assert_eq!(credit_event.event_name, "credit");
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(7500));
+        }
+
+        #[test]
+        fn reverses_debit_whatever_the_status() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            deposit(&store, &account_id, sek(10000)).unwrap();
+            debit(&store, &account_id, sek(10000), "transfer-1").unwrap();
+            close_account(&store, &account_id).unwrap();
+
+            let reversed = reverse_debit(&store, &account_id, sek(10000), "transfer-1", "account closed").unwrap();
+
+            assert_eq!(reversed.event_name, "reverse_debit");
+            assert_eq!(reversed.deltas.get("reason").unwrap(), "account closed");
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(10000));
+        }
+
+        #[test]
+        fn charges_fees_beyond_overdraft_and_pays_interest() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store);
+            freeze_account(&store, &account_id).unwrap();
+
+            let fee_event = charge_fee(&store, &account_id, sek(2500)).unwrap();
+            let interest_event = pay_interest(&store, &account_id, sek(75)).unwrap();
+
+            assert_eq!(fee_event.event_name, "charge_fee");
+            assert_eq!(interest_event.event_name, "pay_interest");
+            assert_eq!(interest_event.deltas.get("amount").unwrap(), "0.75 SEK");
+            assert_eq!(load_account(&store, &account_id).unwrap().0.balance, sek(-2425));
+            assert_eq!(charge_fee(&store, &account_id, sek(-1)), Err(AccountError::InvalidAmount(sek(-1))));
+        }
+
+        #[test]
+        fn unknown_account_is_not_found() {
+            let store = InMemoryEventStore::new();
+
+            assert_eq!(freeze_account(&store, "unknown"), Err(AccountError::AccountNotFound("unknown".into())));
+        }
+
+        fn sek(minor_units: i64) -> Money {
+            Money::new(minor_units, Currency::SEK)
+        }
+
+        fn store_new_account_holder(store: &dyn EventStore) -> String {
+            let event = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            let aggregate_id = event.aggregate_id.clone();
+            store.append(event, 0).unwrap();
+
+            aggregate_id
+        }
+
+        fn store_new_account(store: &dyn EventStore) -> String {
+            let account_holder_id = store_new_account_holder(store);
+            open_account(store, &account_holder_id, Currency::SEK).unwrap().aggregate_id
+        }
+    }
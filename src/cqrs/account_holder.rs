@@ -3,6 +3,27 @@ Implementation of the AccountHolder type events.
 
 Generates 'create', 'read', 'update', and 'delete' type events for AccountHolder.
 
+Changes are made with the `CreateAccountHolder`, `UpdateAccountHolderInfo` and `DeleteAccountHolder` commands,
+handled by the `CommandHandler`, see `cqrs::command`. A deleted AccountHolder can not be changed any more.
+
+Deleting an AccountHolder is a soft delete with a reason: the events stay, and the AccountHolder is marked as
+deleted. It is only allowed once every account of the AccountHolder is closed with a zero balance. Within
+`RETENTION_DAYS` of the deletion, `RestoreAccountHolder` undoes it.
+
+The deltas with personal data are encrypted with a key of the AccountHolder when stored through the
+`EncryptedEventStore`, see `database::encrypted_event_store`. `ForgetAccountHolder` forgets the personal data of
+a deleted AccountHolder for good, by deleting its key: the events stay, but their personal data can not be read
+any more, and the AccountHolder can not be restored. AccountHolders with events stored before personal data was
+encrypted can only be forgotten once `encrypt_stored_personal_data` has encrypted them.
+
+The social security number must be a valid Swedish personal identity number or coordination number, see
+`cqrs::personal_identity_number`, with the same birth date as the date of birth. Both are stored normalized,
+e.g. "930625-7255" and "19930625" are stored as "19930625-7255" and "1993-06-25".
+
+The phone number and home address are value objects, see `cqrs::phone_number` and `cqrs::address`. The command
+to create an AccountHolder takes them as written and parses them, changes carry them parsed. The address is
+stored as the separate deltas `street`, `postal_code`, `city` and `country_code`.
+
 # Example:
 
 Generate event for a new AccountHolder:
@@ -18,47 +39,114 @@ Generate event for a new AccountHolder:
         date_of_birth,
         phone_number,
         home_address,
-    );
+    )?;
 ```
 
 Generate event for updating an existing AccountHolder aggregate:
 ```
-    let account_holder_aggregate = AccountHolder{
-                aggregate_id: aggregate_id,
-                full_name: "Isak Törnros".into(),
-                social_security_number: "199306257255".into(),
-                date_of_birth: "199306257255".into(),
-                phone_number: "0763154177".into(),
-                home_address: "Nöbbelövs Torg 37, 22652 LUND".into(),
-            };
-    let changes = HashMap::from([("full_name".into(), "Emil Törnros")]);
-
-    let new_event = update_account_holder(account_holder_aggregate, changes);
-
-    // this returns:
+    let store = RqlEventStore::open_default()?;
+    let events = store.read_stream(&aggregate_id, "AccountHolder");
+    let account_holder_aggregate = AccountHolder::from_events(&events).unwrap();
+    let changes = AccountHolderChanges {
+        full_name: Some("Emil Törnros".into()),
+        home_address: Some("Storgatan 1, 111 22 Stockholm".parse()?),
+        ..Default::default()
+    };
+
+    let new_event = update_account_holder_info(&store, account_holder_aggregate, changes)?;
+    // or
+    let new_event = CommandHandler::new(&store).handle(&UpdateAccountHolderInfo { aggregate_id, changes })?;
+
+    // this appends and returns:
     //   Event { 
     //       aggregate_id: "C3FFCB03-CDF6-0728-0974-B74DF906A5E6", 
     //       aggregate_version: 2, 
     //       event_name: "update_account_holder_info", 
-    //       timestamp: "2022-08-11 08:18:53.001244 UTC", 
+    //       timestamp: "2022-08-11T08:18:53.001244Z", 
     //       metadata: {},
-    //       deltas: {"full_name": "Emil Törnros"}, 
+    //       deltas: {"full_name": "Emil Törnros", "street": "Storgatan 1", "postal_code": "111 22", "city": "STOCKHOLM", "country_code": "SE"}, 
     //       aggregate_type: "AccountHolder" }
 
 ```
+
+Delete an AccountHolder, and restore it again:
+```
+    delete_account_holder_by_id(&store, aggregate_id.clone(), "requested by the account holder")?;
+
+    restore_account_holder_by_id(&store, aggregate_id)?;
+```
+
+Forget a deleted AccountHolder:
+```
+    let keys = RqlKeyStore::open_default();
+    let store = EncryptedEventStore::new(&events, &keys);
+
+    forget_account_holder_by_id(&store, &keys, aggregate_id)?;
+```
 */
 
 
-use rql::prelude::*;
+use crate::cqrs::account::load_accounts_of_account_holder;
+use crate::cqrs::address::Address;
+use crate::cqrs::clock::{Clock, SystemClock};
+use crate::cqrs::command::{Command, CommandError, CommandHandler};
+use crate::cqrs::domain_event::DomainEvent;
 use crate::cqrs::event::*;
+use crate::cqrs::personal_identity_number::{PersonalIdentityNumber, PersonalIdentityNumberError};
+use crate::cqrs::phone_number::PhoneNumber;
 use std::collections::HashMap;
-use crate::database;
-use crate::database::event_schema::EventSchema;
+use chrono::prelude::*;
+use rql::prelude::*;
+use crate::database::encrypted_event_store::has_plaintext_personal_data;
+use crate::database::event_store::EventStore;
+use crate::database::key_store::KeyStore;
+use crate::projections::account::AccountStatus;
 use crate::projections::account_holder::AccountHolder;
 
 #[allow(dead_code)]
 static AGGREGATE_TYPE: &str = "AccountHolder";
 
+/// How many days after the deletion a deleted AccountHolder can be restored.
+pub static RETENTION_DAYS: i64 = 30;
+
+/// Everything that can happen to an AccountHolder, stored as `event_name` and `deltas` of the `Event`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event_name", rename_all = "snake_case")]
+pub enum AccountHolderEvent {
+    New {
+        full_name: String,
+        social_security_number: String,
+        date_of_birth: String,
+        phone_number: PhoneNumber,
+        #[serde(flatten)]
+        home_address: Address,
+    },
+    UpdateAccountHolderInfo(AccountHolderChanges),
+    DeleteAccountHolder {
+        reason: String,
+    },
+    RestoreAccountHolder,
+    /// The key of the personal data of the AccountHolder is deleted.
+    ForgetAccountHolder,
+}
+
+impl DomainEvent for AccountHolderEvent {
+    const AGGREGATE_TYPE: &'static str = "AccountHolder";
+}
+
+/// The fields of an AccountHolder that are changed by an update. Fields that are None keep their value.
+/// The home address is changed as a whole.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AccountHolderChanges {
+    pub full_name: Option<String>,
+    pub social_security_number: Option<String>,
+    pub date_of_birth: Option<String>,
+    pub phone_number: Option<PhoneNumber>,
+    #[serde(flatten)]
+    pub home_address: Option<Address>,
+}
+
+/// Builds the `new` event of an AccountHolder, without storing it.
 #[allow(dead_code)]
 pub fn create_new_account_holder(
         full_name: &str,
@@ -66,118 +154,323 @@ pub fn create_new_account_holder(
         date_of_birth: &str,
         phone_number: &str,
         home_address: &str,
-        ) -> Event {
+        ) -> Result<Event, CommandError> {
     let metadata = HashMap::from([]);
-    let deltas = HashMap::from([
-        ("full_name".into(), full_name.into()),
-        ("social_security_number".into(), social_security_number.into()),
-        ("date_of_birth".into(), date_of_birth.into()),
-        ("phone_number".into(), phone_number.into()),
-        ("home_address".into(), home_address.into()),
-    ]);
-    let aggregate_type = String::from(AGGREGATE_TYPE);
-
-    let event = Event::new(metadata, deltas, aggregate_type);
-        
-    event
+    let command = CreateAccountHolder {
+        full_name: full_name.into(),
+        social_security_number: social_security_number.into(),
+        date_of_birth: date_of_birth.into(),
+        phone_number: phone_number.into(),
+        home_address: home_address.into(),
+    };
+    command.validate()?;
+
+    let event = Event::from_domain_event(&command.decide(None)?, metadata);
+
+    Ok(event)
 }
 
+/// Creates a new AccountHolder.
 #[allow(dead_code)]
-pub fn update_account_holder(aggregate_id: String, changes: HashMap<String, String>, event_name: &str) -> Option<Event> {
-    println!("update account holder aggregate_id: {}", aggregate_id);
-    let latest_event = get_latest_event_by_aggregate_id(aggregate_id);
-
-    match latest_event {
-        Some(_) => {
-            let metadata = HashMap::new();
-        
-            // Call method 'update' on latest event, generating a brand new and "updated" event based on the one passed in.
-            let new_event = latest_event.unwrap().update(changes, metadata, event_name);
-            return Some(new_event)
-        },
-        None => {
-            return None
+pub struct CreateAccountHolder {
+    pub full_name: String,
+    pub social_security_number: String,
+    pub date_of_birth: String,
+    pub phone_number: String,
+    pub home_address: String,
+}
+
+impl Command for CreateAccountHolder {
+    type State = AccountHolder;
+    type Event = AccountHolderEvent;
+
+    fn aggregate_id(&self) -> Option<&str> {
+        None
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        check_not_blank("full_name", &self.full_name)?;
+        check_identity(&self.social_security_number, &self.date_of_birth)?;
+        parse_contact_details(&self.phone_number, &self.home_address)?;
+
+        Ok(())
+    }
+
+    fn decide(&self, _: Option<&AccountHolder>) -> Result<AccountHolderEvent, CommandError> {
+        let (social_security_number, date_of_birth) = check_identity(&self.social_security_number, &self.date_of_birth)?;
+        let (phone_number, home_address) = parse_contact_details(&self.phone_number, &self.home_address)?;
+
+        Ok(AccountHolderEvent::New {
+            full_name: self.full_name.clone(),
+            social_security_number,
+            date_of_birth,
+            phone_number,
+            home_address,
+        })
+    }
+}
+
+/// Changes the personal information of an AccountHolder that has not been deleted.
+pub struct UpdateAccountHolderInfo {
+    pub aggregate_id: String,
+    pub changes: AccountHolderChanges,
+}
+
+impl Command for UpdateAccountHolderInfo {
+    type State = AccountHolder;
+    type Event = AccountHolderEvent;
+
+    fn aggregate_id(&self) -> Option<&str> {
+        Some(&self.aggregate_id)
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        let AccountHolderChanges { full_name, social_security_number, date_of_birth, phone_number, home_address } = &self.changes;
+        if full_name.is_none() && social_security_number.is_none() && date_of_birth.is_none() && phone_number.is_none() && home_address.is_none() {
+            return Err(CommandError::Validation { field: "changes", reason: "nothing to change".into() })
+        }
+
+        if let Some(full_name) = full_name {
+            check_not_blank("full_name", full_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// A new personal identity number or date of birth is checked against the other one, changed or not.
+    fn decide(&self, account_holder: Option<&AccountHolder>) -> Result<AccountHolderEvent, CommandError> {
+        check_not_deleted(account_holder)?;
+        let mut changes = self.changes.clone();
+
+        if let (Some(account_holder), true) = (account_holder, changes.social_security_number.is_some() || changes.date_of_birth.is_some()) {
+            let (social_security_number, date_of_birth) = check_identity(
+                changes.social_security_number.as_ref().unwrap_or(&account_holder.social_security_number),
+                changes.date_of_birth.as_ref().unwrap_or(&account_holder.date_of_birth),
+            )?;
+            changes.social_security_number = changes.social_security_number.and(Some(social_security_number));
+            changes.date_of_birth = changes.date_of_birth.and(Some(date_of_birth));
+        }
+
+        Ok(AccountHolderEvent::UpdateAccountHolderInfo(changes))
+    }
+}
+
+/// Deletes an AccountHolder that has not been deleted already, and whose accounts are all closed with a zero balance.
+pub struct DeleteAccountHolder {
+    pub aggregate_id: String,
+    pub reason: String,
+}
+
+impl Command for DeleteAccountHolder {
+    type State = AccountHolder;
+    type Event = AccountHolderEvent;
+
+    fn aggregate_id(&self) -> Option<&str> {
+        Some(&self.aggregate_id)
+    }
+
+    fn validate(&self) -> Result<(), CommandError> {
+        check_not_blank("reason", &self.reason)
+    }
+
+    fn check_related(&self, store: &dyn EventStore) -> Result<(), CommandError> {
+        let rejected = |reason: String| CommandError::Rejected {
+            aggregate_type: AccountHolderEvent::AGGREGATE_TYPE,
+            aggregate_id: self.aggregate_id.clone(),
+            reason,
+        };
+
+        for account in load_accounts_of_account_holder(store, &self.aggregate_id) {
+            // closing an account needs a zero balance, but reversed debits can still reach a closed account
+            if account.status != AccountStatus::Closed {
+                return Err(rejected(format!("has open account {}", account.aggregate_id)))
+            }
+            if !account.balance.is_zero() {
+                return Err(rejected(format!("has a balance of {} on account {}", account.balance, account.aggregate_id)))
+            }
+        }
+
+        Ok(())
+    }
+
+    fn decide(&self, account_holder: Option<&AccountHolder>) -> Result<AccountHolderEvent, CommandError> {
+        check_not_deleted(account_holder)?;
+
+        Ok(AccountHolderEvent::DeleteAccountHolder { reason: self.reason.trim().into() })
+    }
+}
+
+/// Undoes the deletion of an AccountHolder, at most `RETENTION_DAYS` after it was deleted.
+pub struct RestoreAccountHolder {
+    pub aggregate_id: String,
+    /// When the restore was asked for, to check the retention period against.
+    pub requested_at: DateTime<Utc>,
+}
+
+impl Command for RestoreAccountHolder {
+    type State = AccountHolder;
+    type Event = AccountHolderEvent;
+
+    fn aggregate_id(&self) -> Option<&str> {
+        Some(&self.aggregate_id)
+    }
+
+    fn decide(&self, account_holder: Option<&AccountHolder>) -> Result<AccountHolderEvent, CommandError> {
+        let rejected = |reason: String| CommandError::Rejected {
+            aggregate_type: AccountHolderEvent::AGGREGATE_TYPE,
+            aggregate_id: self.aggregate_id.clone(),
+            reason,
+        };
+
+        let deleted_at = match account_holder {
+            Some(AccountHolder { forgotten: true, .. }) => return Err(rejected("is forgotten".into())),
+            Some(AccountHolder { deleted: true, deleted_at: Some(deleted_at), .. }) => *deleted_at,
+            _ => return Err(rejected("is not deleted".into())),
+        };
+        if self.requested_at > deleted_at + chrono::Duration::days(RETENTION_DAYS) {
+            return Err(rejected(format!("was deleted more than {} days ago, at {}", RETENTION_DAYS, deleted_at.to_rfc3339())))
+        }
+
+        Ok(AccountHolderEvent::RestoreAccountHolder)
+    }
+}
+
+/// Forgets the personal data of a deleted AccountHolder. Only records that it is forgotten, deleting the key
+/// is up to `forget_account_holder_by_id`.
+pub struct ForgetAccountHolder {
+    pub aggregate_id: String,
+}
+
+impl Command for ForgetAccountHolder {
+    type State = AccountHolder;
+    type Event = AccountHolderEvent;
+
+    fn aggregate_id(&self) -> Option<&str> {
+        Some(&self.aggregate_id)
+    }
+
+    /// Deleting the key does not erase personal data stored in plaintext, so forgetting would only pretend to.
+    fn check_related(&self, store: &dyn EventStore) -> Result<(), CommandError> {
+        let stored_events = store.read_stream_as_stored(&self.aggregate_id, AccountHolderEvent::AGGREGATE_TYPE);
+        if stored_events.iter().any(has_plaintext_personal_data) {
+            return Err(CommandError::Rejected {
+                aggregate_type: AccountHolderEvent::AGGREGATE_TYPE,
+                aggregate_id: self.aggregate_id.clone(),
+                reason: "has personal data that is not encrypted".into(),
+            })
+        }
+
+        Ok(())
+    }
+
+    fn decide(&self, account_holder: Option<&AccountHolder>) -> Result<AccountHolderEvent, CommandError> {
+        let rejected = |reason: &str| CommandError::Rejected {
+            aggregate_type: AccountHolderEvent::AGGREGATE_TYPE,
+            aggregate_id: self.aggregate_id.clone(),
+            reason: reason.into(),
+        };
+
+        match account_holder {
+            Some(AccountHolder { forgotten: true, .. }) => Err(rejected("is already forgotten")),
+            Some(AccountHolder { deleted: true, .. }) => Ok(AccountHolderEvent::ForgetAccountHolder),
+            _ => Err(rejected("is not deleted")),
         }
     }
+}
 
+#[allow(dead_code)]
+pub fn update_account_holder_info(store: &dyn EventStore, aggregate: AccountHolder, changes: AccountHolderChanges) -> Result<Event, CommandError> {
+    update_account_holder_info_by_id(store, aggregate.aggregate_id, changes)
+}
+#[allow(dead_code)]
+pub fn update_account_holder_info_by_id(store: &dyn EventStore, aggregate_id: String, changes: AccountHolderChanges) -> Result<Event, CommandError> {
+    CommandHandler::new(store).handle(&UpdateAccountHolderInfo { aggregate_id, changes })
 }
 #[allow(dead_code)]
-pub fn update_account_holder_info(aggregate: AccountHolder, changes: HashMap<String, String>) -> Option<Event> {
-    let event_name = "update_account_holder_info";
-    update_account_holder(aggregate.aggregate_id, changes, event_name)
+pub fn delete_account_holder(store: &dyn EventStore, aggregate: AccountHolder, reason: &str) -> Result<Event, CommandError> {
+    delete_account_holder_by_id(store, aggregate.aggregate_id, reason)
 }
 #[allow(dead_code)]
-pub fn update_account_holder_info_by_id(aggregate_id: String, changes: HashMap<String, String>) -> Option<Event> {
-    let event_name = "update_account_holder_info";
-    update_account_holder(aggregate_id, changes, event_name)
+pub fn delete_account_holder_by_id(store: &dyn EventStore, aggregate_id: String, reason: &str) -> Result<Event, CommandError> {
+    CommandHandler::new(store).handle(&DeleteAccountHolder { aggregate_id, reason: reason.into() })
 }
 #[allow(dead_code)]
-pub fn delete_account_holder(aggregate: AccountHolder) -> Option<Event> {
-    let changes = HashMap::from([("deltas".into(), "deleted: true".into())]);
-    let event_name = "delete_account_holder";
-    update_account_holder(aggregate.aggregate_id, changes, event_name)
+pub fn restore_account_holder(store: &dyn EventStore, aggregate: AccountHolder) -> Result<Event, CommandError> {
+    restore_account_holder_by_id(store, aggregate.aggregate_id)
 }
 #[allow(dead_code)]
-pub fn delete_account_holder_by_id(aggregate_id: String) -> Option<Event> {
-    let changes = HashMap::from([("deltas".into(), "deleted: true".into())]);
-    let event_name = "delete_account_holder";
-    update_account_holder(aggregate_id, changes, event_name)
+pub fn restore_account_holder_by_id(store: &dyn EventStore, aggregate_id: String) -> Result<Event, CommandError> {
+    CommandHandler::new(store).handle(&RestoreAccountHolder { aggregate_id, requested_at: SystemClock.now() })
+}
+/// Records that the AccountHolder is forgotten, then deletes the key of its personal data.
+#[allow(dead_code)]
+pub fn forget_account_holder_by_id(store: &dyn EventStore, keys: &dyn KeyStore, aggregate_id: String) -> Result<Event, CommandError> {
+    let event = CommandHandler::new(store).handle(&ForgetAccountHolder { aggregate_id: aggregate_id.clone() })?;
+    keys.delete_key(&aggregate_id);
+
+    Ok(event)
 }
 
-fn get_latest_event_by_aggregate_id(aggregate_id: String) -> Option<Event> {
-    let events = get_events_by_id_and_type(aggregate_id, AGGREGATE_TYPE);
+fn check_not_blank(field: &'static str, value: &str) -> Result<(), CommandError> {
+    if value.trim().is_empty() {
+        return Err(CommandError::Validation { field, reason: "must not be blank".into() })
+    }
 
-    println!("events fetched from db: {:?}", &events);
-    println!("latest event: {:?}", events.clone().into_iter().nth(0));
+    Ok(())
+}
 
-    // check if any events were found, if yes, then take first in vec, else early-return a None.
-    let latest = 
-        if events.len() == 0 {
-            println!("events from db was an empty list");
-            return None
-        } else {
-            println!("events from db was not an empty list");
-            Some(events[0].clone())
-        };
+/// Checks that the personal identity number is valid and was given to someone born on the date of birth.
+/// Returns both in the form they are stored in.
+fn check_identity(social_security_number: &str, date_of_birth: &str) -> Result<(String, String), CommandError> {
+    let number: PersonalIdentityNumber = social_security_number.parse().map_err(|error: PersonalIdentityNumberError| CommandError::Validation {
+        field: "social_security_number",
+        reason: error.to_string(),
+    })?;
+    number.check_birth_date(date_of_birth).map_err(|error| CommandError::Validation {
+        field: "date_of_birth",
+        reason: error.to_string(),
+    })?;
+
+    Ok((number.to_string(), number.birth_date().format("%Y-%m-%d").to_string()))
+}
 
-    // check if latest event is already a delete-type event. In that case, return None.
-    if latest.clone().unwrap().event_name == "delete_account_holder" {
-        None
-    } else {
-        latest
-    }
+/// Parses the phone number and home address, as written by the AccountHolder.
+fn parse_contact_details(phone_number: &str, home_address: &str) -> Result<(PhoneNumber, Address), CommandError> {
+    let phone_number = PhoneNumber::parse(phone_number).map_err(|error| CommandError::Validation {
+        field: "phone_number",
+        reason: error.to_string(),
+    })?;
+    let home_address = Address::parse(home_address).map_err(|error| CommandError::Validation {
+        field: "home_address",
+        reason: error.to_string(),
+    })?;
+
+    Ok((phone_number, home_address))
 }
 
-fn get_events_by_id_and_type(aggregate_id: String, aggregate_type: &str) -> Vec<Event> {
-    let schema: EventSchema = database::event_schema::get_schema();
-    let event_table = schema.event();
-
-    let mut events = Vec::new();
-    for event in event_table
-            .wher(|event| 
-                event.aggregate_id == aggregate_id 
-                && event.aggregate_type == aggregate_type
-            )
-            .select(|event| event ) {
-        events.push(event.data.clone());
+fn check_not_deleted(account_holder: Option<&AccountHolder>) -> Result<(), CommandError> {
+    match account_holder {
+        Some(account_holder) if account_holder.deleted => Err(CommandError::Deleted {
+            aggregate_type: AccountHolderEvent::AGGREGATE_TYPE,
+            aggregate_id: account_holder.aggregate_id.clone(),
+        }),
+        _ => Ok(()),
     }
-
-    events
 }
 
 // cargo test -- --nocapture
 #[cfg(test)]
     mod tests {
+        use crate::cqrs::account::AccountEvent;
+        use crate::cqrs::money::{Currency, Money};
+        use crate::cqrs::scenario::{given, id, Scenario};
+        use crate::cqrs::snapshot::rehydrate;
+        use crate::database::encrypted_event_store::{EncryptedEventStore, ERASED};
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::key_store::InMemoryKeyStore;
         use super::*;
 
-        fn setup() -> EventSchema{
-            database::ruql::setup()
-        }
-
-        // using serial testing so that db is populated consistently, to avoid unexpected side effects
-        // when the tests fire off in random order
         #[test]
-        #[serial_test::serial]
         fn test_create_new_account_holder() {
             let full_name = "Isak Törnros".into();
             let social_security_number = "19930625-7255".into();
@@ -191,7 +484,7 @@ fn get_events_by_id_and_type(aggregate_id: String, aggregate_type: &str) -> Vec<
                 date_of_birth,
                 phone_number,
                 home_address,
-            );
+            ).unwrap();
 
             println!("{:?}", event.event_name);
             println!("{:?}", event.deltas.get("full_name"));
@@ -201,75 +494,368 @@ fn get_events_by_id_and_type(aggregate_id: String, aggregate_type: &str) -> Vec<
             assert_eq!(event.deltas.get("full_name"), Some(&String::from(full_name)));
             assert_eq!(event.deltas.get("social_security_number"), Some(&String::from(social_security_number)));
             assert_eq!(event.deltas.get("date_of_birth"), Some(&String::from(date_of_birth)));
-            assert_eq!(event.deltas.get("home_address"), Some(&String::from(home_address)));
+            assert_eq!(event.deltas.get("phone_number"), Some(&String::from("+46763154177")));
+            assert_eq!(event.deltas.get("street"), Some(&String::from("Nöbbelövs Torg 37")));
+            assert_eq!(event.deltas.get("postal_code"), Some(&String::from("226 52")));
+            assert_eq!(event.deltas.get("city"), Some(&String::from("LUND")));
+            assert_eq!(event.deltas.get("country_code"), Some(&String::from("SE")));
+            assert_eq!(event.deltas.get("home_address"), None);
+        }
+
+        #[test]
+        fn test_create_account_holder_command(){
+            let command = CreateAccountHolder {
+                full_name: "Isak Törnros".into(),
+                social_security_number: "19930625-7255".into(),
+                date_of_birth: "1993-06-25".into(),
+                phone_number: "0763-154177".into(),
+                home_address: "Nöbbelövs Torg 37, 22652 LUND, Sweden".into(),
+            };
+
+            let event = Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&command))
+                .then(&[new_account_holder()]);
+
+            assert_eq!(event.aggregate_version, 1);
         }
 
         #[test]
-        #[serial_test::serial]
         fn test_update_account_holder(){
-            setup();
-            let account_holder = get_account_holder();
+            let changes = AccountHolderChanges {
+                full_name: Some("Emil Törnros".into()),
+                ..Default::default()
+            };
+
+            let updated_event = given(&[new_account_holder()])
+                .when(|store| update_account_holder_info(store, get_account_holder(), changes.clone()))
+                .then(&[AccountHolderEvent::UpdateAccountHolderInfo(changes)]);
 
-            let changes = HashMap::from([
-                ("full_name".into(), "Emil Törnros".into())
-            ]);
-            let updated_event = update_account_holder_info(account_holder, changes);
+            println!("Update AccountHolder, event: {:?}", updated_event);
 
-            let updated_event_copy = updated_event.clone().unwrap();
+            assert_eq!(updated_event.event_name, "update_account_holder_info");
+            assert_eq!(updated_event.aggregate_version, 2);
+        }
+
+        #[test]
+        fn test_delete_account_holder(){
+            given(&[new_account_holder()])
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder "))
+                .then(&[deleted()]);
 
-            println!("Update AccountHolder, event: {:?}", updated_event_copy);
+            given(&[new_account_holder()])
+                .when(|store| delete_account_holder(store, get_account_holder(), "requested by the account holder"))
+                .then(&[deleted()]);
 
-            assert_eq!(updated_event_copy.event_name, "update_account_holder_info");
-            assert_eq!(updated_event_copy.deltas.get("full_name").unwrap(), "Emil Törnros");
+            given(&[new_account_holder()])
+                .when(|store| delete_account_holder_by_id(store, id(1), " "))
+                .then_error(CommandError::Validation { field: "reason", reason: "must not be blank".into() });
         }
 
         #[test]
-        #[serial_test::serial]
-        fn test_get_latest_event(){
-            let aggregate_id = get_random_accountholder_id_from_db();
+        fn test_account_holder_with_open_accounts_can_not_be_deleted(){
+            let account = |events: &[AccountEvent]| [&[AccountEvent::New { account_holder_id: id(1), currency: Currency::SEK }], events].concat();
+            let rejected = |reason: String| CommandError::Rejected { aggregate_type: "AccountHolder", aggregate_id: id(1), reason };
+
+            given(&[new_account_holder()])
+                .given(&account(&[AccountEvent::CloseAccount]))
+                .given(&account(&[AccountEvent::FreezeAccount]))
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder"))
+                .then_error(rejected(format!("has open account {}", id(3))));
+
+            // e.g. a debit reversed after the account was closed
+            let amount = Money::new(10000, Currency::SEK);
+            given(&[new_account_holder()])
+                .given(&account(&[AccountEvent::CloseAccount, AccountEvent::ReverseDebit { amount, transfer_id: "transfer-1".into(), reason: "".into() }]))
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder"))
+                .then_error(rejected(format!("has a balance of 100.00 SEK on account {}", id(2))));
+
+            // accounts of other account holders do not matter
+            given(&[new_account_holder()])
+                .given(&account(&[AccountEvent::CloseAccount]))
+                .given(&[new_account_holder()])
+                .given(&[AccountEvent::New { account_holder_id: id(3), currency: Currency::SEK }])
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder"))
+                .then(&[deleted()]);
+        }
 
-            let latest_event = get_latest_event_by_aggregate_id(aggregate_id);
+        #[test]
+        fn test_restore_account_holder_within_retention_period(){
+            let deleted_at = Utc.with_ymd_and_hms(2022, 8, 11, 8, 0, 0).unwrap();
+            let restore = |days: i64| RestoreAccountHolder { aggregate_id: id(1), requested_at: deleted_at + chrono::Duration::days(days) };
+
+            let event = given(&[new_account_holder(), deleted()])
+                .when(|store| CommandHandler::new(store).handle(&restore(RETENTION_DAYS)))
+                .then(&[AccountHolderEvent::RestoreAccountHolder]);
+            assert_eq!(event.aggregate_version, 3);
+
+            given(&[new_account_holder(), deleted()])
+                .when(|store| CommandHandler::new(store).handle(&restore(RETENTION_DAYS + 1)))
+                .then_error(CommandError::Rejected {
+                    aggregate_type: "AccountHolder",
+                    aggregate_id: id(1),
+                    reason: "was deleted more than 30 days ago, at 2022-08-11T08:00:00+00:00".into(),
+                });
+
+            given(&[new_account_holder()])
+                .when(|store| CommandHandler::new(store).handle(&restore(0)))
+                .then_error(CommandError::Rejected { aggregate_type: "AccountHolder", aggregate_id: id(1), reason: "is not deleted".into() });
+        }
 
-            assert_eq!(latest_event.unwrap().event_name, "new"); 
+        #[test]
+        fn test_restored_account_holder_can_be_changed(){
+            let store = InMemoryEventStore::new();
+            let aggregate_id = store_new_account_holder(&store);
+            delete_account_holder_by_id(&store, aggregate_id.clone(), "requested by the account holder").unwrap();
+
+            restore_account_holder_by_id(&store, aggregate_id.clone()).unwrap();
+
+            let changes = AccountHolderChanges { full_name: Some("Emil Törnros".into()), ..Default::default() };
+            let (account_holder, _) = rehydrate::<AccountHolder>(&store, &aggregate_id).unwrap();
+            assert!(!account_holder.deleted);
+            assert_eq!(account_holder.deleted_at, None);
+            assert!(update_account_holder_info_by_id(&store, aggregate_id, changes).is_ok());
         }
 
         #[test]
-        #[serial_test::serial]
-        fn test_delete_account_holder(){
-            let account_holder = get_account_holder();
+        fn test_forget_deleted_account_holder(){
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let aggregate_id = store_new_account_holder(&store);
+            let rejected = |reason: &str| Err(CommandError::Rejected { aggregate_type: "AccountHolder", aggregate_id: aggregate_id.clone(), reason: reason.into() });
+            let forget = ForgetAccountHolder { aggregate_id: aggregate_id.clone() };
+            let handler = CommandHandler::new(&store);
+
+            assert_eq!(handler.handle(&forget), rejected("is not deleted"));
+            delete_account_holder_by_id(&store, aggregate_id.clone(), "requested by the account holder").unwrap();
+            assert!(handler.handle(&forget).is_ok());
+            assert_eq!(handler.handle(&forget), rejected("is already forgotten"));
+            assert_eq!(restore_account_holder_by_id(&store, aggregate_id.clone()), rejected("is forgotten"));
+        }
 
-            let delete_event_1 = delete_account_holder_by_id(account_holder.aggregate_id.clone());
-            let delete_event_2 = delete_account_holder(account_holder);
+        #[test]
+        fn test_account_holder_with_personal_data_in_plaintext_can_not_be_forgotten(){
+            // the scenario stores events without encrypting them, like before personal data was encrypted
+            given(&[new_account_holder(), deleted()])
+                .when(|store| CommandHandler::new(store).handle(&ForgetAccountHolder { aggregate_id: id(1) }))
+                .then_error(CommandError::Rejected {
+                    aggregate_type: "AccountHolder",
+                    aggregate_id: id(1),
+                    reason: "has personal data that is not encrypted".into(),
+                });
+        }
+
+        #[test]
+        fn test_forgotten_account_holder_can_not_be_read(){
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let aggregate_id = store_new_account_holder(&store);
+            delete_account_holder_by_id(&store, aggregate_id.clone(), "requested by the account holder").unwrap();
+
+            forget_account_holder_by_id(&store, &keys, aggregate_id.clone()).unwrap();
+
+            let (account_holder, _) = rehydrate::<AccountHolder>(&store, &aggregate_id).unwrap();
+            assert!(account_holder.forgotten);
+            assert_eq!(account_holder.full_name, "");
+            assert_eq!(keys.key(&aggregate_id), None);
+            let new_event = store.read_stream(&aggregate_id, "AccountHolder").remove(0);
+            assert_eq!(new_event.deltas["full_name"], ERASED);
+            assert_eq!(new_event.deltas["social_security_number"], ERASED);
+        }
+
+        #[test]
+        fn test_deleted_account_holder_can_not_be_changed(){
+            let changes = AccountHolderChanges { full_name: Some("Emil Törnros".into()), ..Default::default() };
+            let deleted_error = CommandError::Deleted { aggregate_type: "AccountHolder", aggregate_id: id(1) };
 
-            // println!("delete_event_1: {:?}", delete_event_1);
-            // println!("delete_event_2: {:?}", delete_event_2);
+            given(&[new_account_holder(), deleted()])
+                .when(|store| update_account_holder_info_by_id(store, id(1), changes))
+                .then_error(deleted_error.clone());
 
-            assert_eq!(delete_event_1.unwrap().event_name, "delete_account_holder");
-            assert_eq!(delete_event_2.unwrap().event_name, "delete_account_holder");
+            given(&[new_account_holder(), deleted()])
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder"))
+                .then_error(deleted_error);
         }
 
-        fn get_random_accountholder_id_from_db() -> String {
-            let db = database::event_schema::get_schema();
-            let table = db.event();
+        #[test]
+        fn test_unknown_account_holder_is_not_found(){
+            Scenario::new()
+                .when(|store| delete_account_holder_by_id(store, id(1), "requested by the account holder"))
+                .then_error(CommandError::NotFound { aggregate_type: "AccountHolder", aggregate_id: id(1) });
+        }
+
+        #[test]
+        fn test_rejects_invalid_commands(){
+            let blank_name = AccountHolderChanges { full_name: Some(" ".into()), ..Default::default() };
+
+            given(&[new_account_holder()])
+                .when(|store| update_account_holder_info_by_id(store, id(1), AccountHolderChanges::default()))
+                .then_error(CommandError::Validation { field: "changes", reason: "nothing to change".into() });
+
+            given(&[new_account_holder()])
+                .when(|store| update_account_holder_info_by_id(store, id(1), blank_name))
+                .then_error(CommandError::Validation { field: "full_name", reason: "must not be blank".into() });
+        }
+
+        #[test]
+        fn test_rejects_invalid_identity(){
+            let create = |social_security_number: &str, date_of_birth: &str| CreateAccountHolder {
+                full_name: "Isak Törnros".into(),
+                social_security_number: social_security_number.into(),
+                date_of_birth: date_of_birth.into(),
+                phone_number: "0763-154177".into(),
+                home_address: "Nöbbelövs Torg 37, 22652 LUND, Sweden".into(),
+            };
+
+            Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&create("19930625-7256", "1993-06-25")))
+                .then_error(CommandError::Validation {
+                    field: "social_security_number",
+                    reason: "'19930625-7256' has the wrong check digit".into(),
+                });
+
+            Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&create("19930625-7255", "1993-06-26")))
+                .then_error(CommandError::Validation {
+                    field: "date_of_birth",
+                    reason: "19930625-7255 does not belong to someone born on 1993-06-26".into(),
+                });
+
+            // the new date of birth is checked against the number that is already stored
+            let changes = AccountHolderChanges { date_of_birth: Some("1993-06-26".into()), ..Default::default() };
+            given(&[new_account_holder()])
+                .when(|store| update_account_holder_info_by_id(store, id(1), changes))
+                .then_error(CommandError::Validation {
+                    field: "date_of_birth",
+                    reason: "19930625-7255 does not belong to someone born on 1993-06-26".into(),
+                });
+        }
+
+        #[test]
+        fn test_stores_normalized_identity(){
+            let command = CreateAccountHolder {
+                full_name: "Isak Törnros".into(),
+                social_security_number: "930625-7255".into(),
+                date_of_birth: "19930625".into(),
+                phone_number: "0763-154177".into(),
+                home_address: "Nöbbelövs Torg 37, 22652 LUND, Sweden".into(),
+            };
+            Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&command))
+                .then(&[new_account_holder()]);
+
+            // a coordination number has 60 added to the day of birth
+            let changes = AccountHolderChanges {
+                social_security_number: Some("7010632391".into()),
+                date_of_birth: Some("1970-10-03".into()),
+                ..Default::default()
+            };
+            given(&[new_account_holder()])
+                .when(|store| update_account_holder_info_by_id(store, id(1), changes))
+                .then(&[AccountHolderEvent::UpdateAccountHolderInfo(AccountHolderChanges {
+                    social_security_number: Some("19701063-2391".into()),
+                    date_of_birth: Some("1970-10-03".into()),
+                    ..Default::default()
+                })]);
+        }
+
+        #[test]
+        fn test_rejects_invalid_contact_details(){
+            let create = |phone_number: &str, home_address: &str| CreateAccountHolder {
+                full_name: "Isak Törnros".into(),
+                social_security_number: "19930625-7255".into(),
+                date_of_birth: "1993-06-25".into(),
+                phone_number: phone_number.into(),
+                home_address: home_address.into(),
+            };
 
-            let events: Vec<_> =
-                table
-                .wher(|event| event.aggregate_type == AGGREGATE_TYPE)
-                .select(|event| event.aggregate_id.clone() )
-                .collect();
+            Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&create("763154177", "Nöbbelövs Torg 37, 22652 LUND")))
+                .then_error(CommandError::Validation {
+                    field: "phone_number",
+                    reason: "'763154177' is not a phone number".into(),
+                });
+
+            Scenario::new()
+                .when(|store| CommandHandler::new(store).handle(&create("0763-154177", "Nöbbelövs Torg 37, 2265 LUND")))
+                .then_error(CommandError::Validation {
+                    field: "home_address",
+                    reason: "'2265' is not a postal code".into(),
+                });
+        }
+
+        #[test]
+        fn test_update_builds_on_tail_of_multi_update_stream(){
+            let store = InMemoryEventStore::new();
+            let aggregate_id = store_new_account_holder(&store);
+
+            for (expected_version, name) in [(1, "Emil Törnros"), (2, "Ida Törnros"), (3, "Olle Törnros")] {
+                let changes = AccountHolderChanges { full_name: Some(name.into()), ..Default::default() };
+                let updated_event = update_account_holder_info_by_id(&store, aggregate_id.clone(), changes).unwrap();
+
+                assert_eq!(updated_event.aggregate_version, expected_version + 1);
+            }
+
+            let latest_event = store.last_event(&aggregate_id, AGGREGATE_TYPE).unwrap();
+
+            assert_eq!(latest_event.aggregate_version, 4);
+            assert_eq!(latest_event.deltas.get("full_name").unwrap(), "Olle Törnros");
+        }
+
+        #[test]
+        fn test_delete_after_multiple_updates(){
+            let store = InMemoryEventStore::new();
+            let aggregate_id = store_new_account_holder(&store);
+            for expected_version in 1..3 {
+                let changes = AccountHolderChanges { phone_number: Some(format!("076315417{}", expected_version).parse().unwrap()), ..Default::default() };
+                update_account_holder_info_by_id(&store, aggregate_id.clone(), changes).unwrap();
+            }
+
+            let delete_event = delete_account_holder_by_id(&store, aggregate_id.clone(), "requested by the account holder").unwrap();
+            assert_eq!(delete_event.aggregate_version, 4);
+
+            let changes = AccountHolderChanges { full_name: Some("Emil Törnros".into()), ..Default::default() };
+
+            assert!(matches!(update_account_holder_info_by_id(&store, aggregate_id, changes), Err(CommandError::Deleted { .. })));
+            assert_eq!(store.latest_version(&delete_event.aggregate_id, AGGREGATE_TYPE), 4);
+        }
+
+        fn store_new_account_holder(store: &dyn EventStore) -> String {
+            let event = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            let aggregate_id = event.aggregate_id.clone();
+            store.append(event, 0).unwrap();
+
+            aggregate_id
+        }
+
+        fn new_account_holder() -> AccountHolderEvent {
+            AccountHolderEvent::New {
+                full_name: "Isak Törnros".into(),
+                social_security_number: "19930625-7255".into(),
+                date_of_birth: "1993-06-25".into(),
+                phone_number: "0763-154177".parse().unwrap(),
+                home_address: "Nöbbelövs Torg 37, 22652 LUND, Sweden".parse().unwrap(),
+            }
+        }
 
-            events.into_iter().nth(0).unwrap()
+        fn deleted() -> AccountHolderEvent {
+            AccountHolderEvent::DeleteAccountHolder { reason: "requested by the account holder".into() }
         }
 
         fn get_account_holder() -> AccountHolder {
-            let aggregate_id = get_random_accountholder_id_from_db();
             AccountHolder{
-                aggregate_id: aggregate_id,
+                aggregate_id: id(1),
                 full_name: "Isak Törnros".into(),
                 social_security_number: "199306257255".into(),
                 date_of_birth: "199306257255".into(),
-                phone_number: "0763154177".into(),
-                home_address: "Nöbbelövs Torg 37, 22652 LUND".into(),
+                phone_number: "0763154177".parse().unwrap(),
+                home_address: "Nöbbelövs Torg 37, 22652 LUND".parse().unwrap(),
+                ..Default::default()
             }
         }
     }
\ No newline at end of file
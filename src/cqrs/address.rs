@@ -0,0 +1,222 @@
+/**
+Postal address: street, postal code, city and ISO 3166-1 alpha-2 country code.
+
+Addresses are parsed from one line, written the way they are on an envelope with commas between the lines,
+e.g. "Nöbbelövs Torg 37, 22652 Lund, Sweden". The country is optional and defaults to Sweden, and can be given
+as a code or as "Sweden" or "Sverige". Everything before the postal code line is the street, so a "c/o" line
+is kept with the street.
+
+Swedish addresses are normalized the way PostNord wants them: postal codes are five digits with a space after
+the third, e.g. "226 52", with or without the "SE-" prefix when parsed, and the city is written in capitals.
+Addresses in other countries get the first word of the postal code line as postal code, and are not checked
+any further.
+
+Addresses are stored in event deltas as the separate fields `street`, `postal_code`, `city` and `country_code`.
+Stored addresses are read back as they are, see `PhoneNumber` for why.
+
+# Example:
+```
+    let address: Address = "Nöbbelövs Torg 37, 22652 Lund, Sweden".parse()?;
+
+    assert_eq!(address.postal_code(), "226 52");
+    assert_eq!(address.to_string(), "Nöbbelövs Torg 37, 226 52 LUND, SE");
+```
+*/
+
+use std::fmt;
+use std::str::FromStr;
+use rql::prelude::*;
+
+static SWEDEN: &str = "SE";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AddressError {
+    /// Not a street and a postal code line separated by a comma, or a part of the address is missing.
+    InvalidFormat(String),
+    InvalidPostalCode(String),
+    /// Not a two letter country code, or a country name that is known.
+    UnknownCountry(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::InvalidFormat(address) => write!(f, "'{}' is not an address", address),
+            AddressError::InvalidPostalCode(postal_code) => write!(f, "'{}' is not a postal code", postal_code),
+            AddressError::UnknownCountry(country) => write!(f, "unknown country '{}'", country),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Address {
+    street: String,
+    postal_code: String,
+    city: String,
+    country_code: String,
+}
+
+impl Address {
+    /// Checks and normalizes the parts of an address. `country` is a code or a known country name.
+    pub fn new(street: &str, postal_code: &str, city: &str, country: &str) -> Result<Address, AddressError> {
+        let country_code = country_code(country).ok_or_else(|| AddressError::UnknownCountry(country.into()))?;
+        let (street, city) = (collapse_whitespace(street), collapse_whitespace(city));
+        if street.is_empty() || city.is_empty() {
+            return Err(AddressError::InvalidFormat(format!("{}, {} {}", street, postal_code, city)))
+        }
+
+        let postal_code = match country_code.as_str() {
+            "SE" => swedish_postal_code(postal_code).ok_or_else(|| AddressError::InvalidPostalCode(postal_code.into()))?,
+            _ => collapse_whitespace(postal_code),
+        };
+        let city = if country_code == SWEDEN { city.to_uppercase() } else { city };
+
+        Ok(Address { street, postal_code, city, country_code })
+    }
+
+    /// Parses "<street>, <postal code> <city>[, <country>]".
+    pub fn parse(address: &str) -> Result<Address, AddressError> {
+        let invalid_format = || AddressError::InvalidFormat(address.into());
+        let mut lines: Vec<&str> = address.split(',').map(str::trim).collect();
+
+        // the postal code line always has digits, a country never does
+        let country = match lines.last() {
+            Some(last) if lines.len() > 2 && !last.bytes().any(|char| char.is_ascii_digit()) => lines.pop().unwrap(),
+            _ => SWEDEN,
+        };
+        let postal_code_line = lines.pop().ok_or_else(invalid_format)?;
+        let street = lines.join(", ");
+        if street.is_empty() {
+            return Err(invalid_format())
+        }
+
+        let words: Vec<&str> = postal_code_line.split_whitespace().collect();
+        let is_digits = |word: &str, count: usize| word.len() == count && word.bytes().all(|char| char.is_ascii_digit());
+        let split_at = match words.as_slice() {
+            // "SE-226 52 LUND"
+            [first, second, _, ..] if country_code(country).as_deref() == Some(SWEDEN)
+                && is_digits(without_prefix(first), 3) && is_digits(second, 2) => 2,
+            [_, _, ..] => 1,
+            _ => return Err(invalid_format()),
+        };
+
+        Address::new(&street, &words[..split_at].join(" "), &words[split_at..].join(" "), country)
+    }
+
+    #[allow(dead_code)]
+    pub fn street(&self) -> &str {
+        &self.street
+    }
+
+    pub fn postal_code(&self) -> &str {
+        &self.postal_code
+    }
+
+    #[allow(dead_code)]
+    pub fn city(&self) -> &str {
+        &self.city
+    }
+
+    #[allow(dead_code)]
+    pub fn country_code(&self) -> &str {
+        &self.country_code
+    }
+}
+
+/// The ISO 3166-1 alpha-2 code of a country code or a known country name.
+fn country_code(country: &str) -> Option<String> {
+    let country = country.trim().to_uppercase();
+
+    match country.as_str() {
+        "SE" | "SWE" | "SWEDEN" | "SVERIGE" => Some(SWEDEN.into()),
+        code if code.len() == 2 && code.bytes().all(|char| char.is_ascii_uppercase()) => Some(country),
+        _ => None,
+    }
+}
+
+/// "NNN NN", from five digits with an optional space and "SE-" or "S-" prefix. Swedish postal codes never start with 0.
+fn swedish_postal_code(postal_code: &str) -> Option<String> {
+    let digits = without_prefix(postal_code.trim()).replace(' ', "");
+
+    match digits.as_bytes() {
+        [b'1'..=b'9', ..] if digits.len() == 5 && digits.bytes().all(|digit| digit.is_ascii_digit()) => {
+            Some(format!("{} {}", &digits[..3], &digits[3..]))
+        },
+        _ => None,
+    }
+}
+
+fn without_prefix(postal_code: &str) -> &str {
+    ["SE-", "se-", "S-", "s-"]
+        .iter()
+        .find_map(|prefix| postal_code.strip_prefix(prefix))
+        .unwrap_or(postal_code)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}, {} {}, {}", self.street, self.postal_code, self.city, self.country_code)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(address: &str) -> Result<Address, AddressError> {
+        Address::parse(address)
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_swedish_addresses() {
+            for address in [
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+                "Nöbbelövs Torg 37, 226 52 Lund",
+                "Nöbbelövs  Torg 37 , SE-226 52 Lund, Sverige",
+                "Nöbbelövs Torg 37, 22652 lund, se",
+            ] {
+                let parsed = Address::parse(address).unwrap();
+
+                assert_eq!(parsed, Address::new("Nöbbelövs Torg 37", "226 52", "LUND", "SE").unwrap());
+                assert_eq!(parsed.to_string(), "Nöbbelövs Torg 37, 226 52 LUND, SE");
+            }
+        }
+
+        #[test]
+        fn keeps_care_of_line_with_street() {
+            let parsed = Address::parse("c/o Svensson, Storgatan 1, 111 22 Stockholm").unwrap();
+
+            assert_eq!(parsed.street(), "c/o Svensson, Storgatan 1");
+            assert_eq!(parsed.city(), "STOCKHOLM");
+        }
+
+        #[test]
+        fn parses_addresses_in_other_countries() {
+            let parsed = Address::parse("Karl Johans gate 1, 0154 Oslo, NO").unwrap();
+
+            assert_eq!(parsed.postal_code(), "0154");
+            assert_eq!(parsed.city(), "Oslo");
+            assert_eq!(parsed.country_code(), "NO");
+        }
+
+        #[test]
+        fn rejects_invalid_addresses() {
+            assert_eq!(Address::parse("Storgatan 1, 01122 Stockholm"), Err(AddressError::InvalidPostalCode("01122".into())));
+            assert_eq!(Address::parse("Storgatan 1, 1112 Stockholm"), Err(AddressError::InvalidPostalCode("1112".into())));
+            assert_eq!(Address::parse("Storgatan 1, 111 22 Stockholm, Norrland"), Err(AddressError::UnknownCountry("Norrland".into())));
+            for address in ["Storgatan 1 111 22 Stockholm", ", 111 22 Stockholm", "Storgatan 1, 11122"] {
+                assert_eq!(Address::parse(address), Err(AddressError::InvalidFormat(address.into())));
+            }
+        }
+    }
@@ -0,0 +1,92 @@
+/**
+Where events get their timestamps from.
+
+Events are timestamped by a `Clock` instead of calling `Utc::now()` directly, so tests can fix the time and
+assert exact timestamps. `SystemClock` is the real time, `FixedClock` stands still until it is moved.
+
+# Example:
+```
+    let clock = FixedClock::new(Utc.with_ymd_and_hms(2022, 8, 11, 8, 0, 0).unwrap());
+    let events = EventFactory::new(&clock, &SequentialIds::new());
+
+    let first = events.new_event(metadata, deltas, "Account".into());
+    clock.advance(Duration::days(1));
+    let second = events.update(&first, changes, HashMap::new(), "deposit");
+```
+*/
+
+use std::sync::Mutex;
+use chrono::{Duration, SubsecRound};
+use chrono::prelude::*;
+
+pub trait Clock: Send + Sync {
+    /// The current time, with the microsecond precision timestamps are stored with.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real time.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now().trunc_subsecs(6)
+    }
+}
+
+/// A clock that shows the same time until it is set or advanced, for tests.
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FixedClock {
+    #[allow(dead_code)]
+    pub fn new(now: DateTime<Utc>) -> FixedClock {
+        FixedClock { now: Mutex::new(now.trunc_subsecs(6)) }
+    }
+
+    #[allow(dead_code)]
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().expect("Thread using fixed clock panicked") = now.trunc_subsecs(6);
+    }
+
+    #[allow(dead_code)]
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().expect("Thread using fixed clock panicked");
+        *now = (*now + duration).trunc_subsecs(6);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().expect("Thread using fixed clock panicked")
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn fixed_clock_stands_still_until_moved() {
+            let start = Utc.with_ymd_and_hms(2022, 8, 11, 8, 0, 0).unwrap();
+            let clock = FixedClock::new(start);
+
+            assert_eq!(clock.now(), start);
+            assert_eq!(clock.now(), start);
+
+            clock.advance(Duration::days(1));
+            assert_eq!(clock.now(), Utc.with_ymd_and_hms(2022, 8, 12, 8, 0, 0).unwrap());
+
+            clock.set(start);
+            assert_eq!(clock.now(), start);
+        }
+
+        #[test]
+        fn system_clock_has_microsecond_precision() {
+            let now = SystemClock.now();
+
+            assert!(now <= Utc::now());
+            assert_eq!(now.nanosecond() % 1000, 0);
+        }
+    }
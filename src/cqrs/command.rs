@@ -0,0 +1,181 @@
+/**
+Commands, and the handler that turns them into events.
+
+A command is a request to change one aggregate, e.g. `UpdateAccountHolderInfo`. The `CommandHandler` handles
+every command the same way:
+1. validate the command on its own, without looking at any state
+2. check the other aggregates the command depends on, if any, e.g. the accounts of an AccountHolder
+3. load the current state of the aggregate from its snapshot and events, unless the command creates a new one
+4. let the command decide which event happens, given that state
+5. append the event, on top of the version the state was loaded at
+
+Every failure is a `CommandError`, so callers can tell a missing aggregate from a deleted one, an invalid
+command, a command the state does not allow, or a concurrent change that they may retry.
+
+# Example:
+```
+    let handler = CommandHandler::new(&store);
+
+    match handler.handle(&UpdateAccountHolderInfo { aggregate_id, changes }) {
+        Ok(event) => println!("updated to version {}", event.aggregate_version),
+        Err(CommandError::Deleted { .. }) => println!("the account holder has been deleted"),
+        Err(error) => println!("{}", error),
+    }
+```
+*/
+
+use std::collections::HashMap;
+use thiserror::Error;
+use crate::cqrs::domain_event::DomainEvent;
+use crate::cqrs::event::{Event, EventFactory};
+use crate::cqrs::snapshot::{rehydrate, Snapshotable};
+use crate::database::event_store::{AppendError, EventStore};
+
+pub trait Command {
+    /// The state the command is decided on.
+    type State: Snapshotable;
+
+    /// The events the command results in.
+    type Event: DomainEvent;
+
+    /// The aggregate the command changes, or None if the command creates a new aggregate.
+    fn aggregate_id(&self) -> Option<&str>;
+
+    /// Checks the command itself, before the aggregate is loaded.
+    fn validate(&self) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    /// Checks the other aggregates the command depends on. They are not locked, so this only catches
+    /// changes to them that were stored before the command was handled.
+    fn check_related(&self, _store: &dyn EventStore) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    /// Decides which event happens. `state` is None for commands that create a new aggregate.
+    fn decide(&self, state: Option<&Self::State>) -> Result<Self::Event, CommandError>;
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// There is no aggregate with the id.
+    #[error("{} {} not found", aggregate_name(.aggregate_type), .aggregate_id)]
+    NotFound {
+        aggregate_type: &'static str,
+        aggregate_id: String,
+    },
+    /// The aggregate exists, but has been deleted.
+    #[error("{} {} has been deleted", aggregate_name(.aggregate_type), .aggregate_id)]
+    Deleted {
+        aggregate_type: &'static str,
+        aggregate_id: String,
+    },
+    /// The command is not valid, on its own or in the current state of the aggregate.
+    #[error("invalid {field}: {reason}")]
+    Validation {
+        field: &'static str,
+        reason: String,
+    },
+    /// The command is valid, but not allowed in the current state of the aggregate or the aggregates it depends on.
+    #[error("{} {} {}", aggregate_name(.aggregate_type), .aggregate_id, .reason)]
+    Rejected {
+        aggregate_type: &'static str,
+        aggregate_id: String,
+        reason: String,
+    },
+    /// Someone else changed the aggregate since it was loaded. Handling the command again may succeed.
+    #[error("{0}")]
+    Concurrency(#[source] AppendError),
+    /// The event store did not accept the event for another reason.
+    #[error("can not store event: {0}")]
+    Storage(#[source] AppendError),
+}
+
+/// The aggregate type as it is written in messages, e.g. "account holder" for "AccountHolder".
+fn aggregate_name(aggregate_type: &str) -> String {
+    let mut name = String::new();
+    for (i, c) in aggregate_type.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            name.push(' ');
+        }
+        name.extend(c.to_lowercase());
+    }
+
+    name
+}
+
+impl From<AppendError> for CommandError {
+    fn from(error: AppendError) -> CommandError {
+        match error {
+            AppendError::Conflict { .. } => CommandError::Concurrency(error),
+            AppendError::InvalidVersion { .. } | AppendError::BrokenLog(_) => CommandError::Storage(error),
+        }
+    }
+}
+
+/// Handles commands against an event store.
+pub struct CommandHandler<'a> {
+    store: &'a dyn EventStore,
+    events: EventFactory<'a>,
+}
+
+impl<'a> CommandHandler<'a> {
+    /// Creates events with the system clock and random ids.
+    pub fn new(store: &'a dyn EventStore) -> CommandHandler<'a> {
+        CommandHandler::with_factory(store, EventFactory::system())
+    }
+
+    #[allow(dead_code)]
+    pub fn with_factory(store: &'a dyn EventStore, events: EventFactory<'a>) -> CommandHandler<'a> {
+        CommandHandler { store, events }
+    }
+
+    /// Validates, checks, loads, decides and appends. Returns the appended event.
+    pub fn handle<C: Command>(&self, command: &C) -> Result<Event, CommandError> {
+        command.validate()?;
+        command.check_related(self.store)?;
+
+        let event = match command.aggregate_id() {
+            Some(aggregate_id) => {
+                let (state, latest_event) = rehydrate::<C::State>(self.store, aggregate_id).ok_or_else(|| CommandError::NotFound {
+                    aggregate_type: C::State::AGGREGATE_TYPE,
+                    aggregate_id: aggregate_id.into(),
+                })?;
+                let domain_event = command.decide(Some(&state))?;
+
+                self.events.update_with(&latest_event, &domain_event, HashMap::new())
+            },
+            None => self.events.new_with(&command.decide(None)?, HashMap::new()),
+        };
+
+        self.store.append(event.clone(), event.aggregate_version - 1)?;
+
+        Ok(event)
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn conflicts_are_concurrency_errors() {
+            let conflict = AppendError::Conflict { aggregate_id: "a".into(), expected_version: 1, actual_version: 2 };
+            let invalid = AppendError::InvalidVersion { aggregate_id: "a".into(), expected_version: 1, event_version: 3 };
+
+            assert_eq!(CommandError::from(conflict.clone()), CommandError::Concurrency(conflict));
+            assert_eq!(CommandError::from(invalid.clone()), CommandError::Storage(invalid));
+        }
+
+        #[test]
+        fn describes_errors() {
+            let not_found = CommandError::NotFound { aggregate_type: "AccountHolder", aggregate_id: "a".into() };
+            let invalid = CommandError::Validation { field: "full_name", reason: "must not be blank".into() };
+            let rejected = CommandError::Rejected { aggregate_type: "AccountHolder", aggregate_id: "a".into(), reason: "is not deleted".into() };
+
+            assert_eq!(not_found.to_string(), "account holder a not found");
+            assert_eq!(invalid.to_string(), "invalid full_name: must not be blank");
+            assert_eq!(rejected.to_string(), "account holder a is not deleted");
+        }
+    }
@@ -0,0 +1,175 @@
+/**
+Typed domain events, stored in the `Event` envelope.
+
+Every aggregate type has an enum of the events that can happen to it, e.g. `AccountEvent`, and implements
+`DomainEvent` for it. The enums are serialized with serde, tagged with `event_name`: the name of the variant
+in snake_case becomes the `event_name` of the envelope, and its fields become the `deltas`. The stored events
+look exactly as before, so events written before the enums existed decode just the same.
+
+Commands build their events from the enums, and projections decode the envelope back into the enum and match
+on it exhaustively, so a misspelled event name or a forgotten event is a compile error.
+
+# Example:
+```
+    let deposited = latest_event.update_with(&AccountEvent::Deposit { amount }, HashMap::new());
+
+    match deposited.decode::<AccountEvent>()? {
+        AccountEvent::Deposit { amount } => println!("deposited {}", amount),
+        _ => {}
+    }
+```
+*/
+
+use std::fmt;
+use std::collections::HashMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use crate::cqrs::event::Event;
+
+/// The events of one aggregate type. Implementors are enums tagged with `event_name`, i.e.
+/// `#[serde(tag = "event_name", rename_all = "snake_case")]`, whose fields all serialize as strings.
+pub trait DomainEvent: Serialize + DeserializeOwned {
+    /// The `aggregate_type` of the streams these events are stored in.
+    const AGGREGATE_TYPE: &'static str;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// The event belongs to another type of aggregate.
+    WrongAggregateType {
+        expected: &'static str,
+        actual: String,
+    },
+    /// Unknown event name, or deltas that do not fit the event.
+    Invalid {
+        event_name: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::WrongAggregateType { expected, actual } => write!(
+                f,
+                "expected an event of a {} aggregate, got one of a {} aggregate",
+                expected, actual
+            ),
+            DecodeError::Invalid { event_name, reason } => write!(f, "can not decode event {}: {}", event_name, reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Splits a domain event into the `event_name` and `deltas` of the envelope.
+pub fn encode<E: DomainEvent>(domain_event: &E) -> (String, HashMap<String, String>) {
+    let fields = match serde_json::to_value(domain_event) {
+        Ok(Value::Object(fields)) => fields,
+        other => panic!("domain events of {} must serialize to a map, got {:?}", E::AGGREGATE_TYPE, other),
+    };
+
+    let mut deltas: HashMap<String, String> = fields
+        .into_iter()
+        .filter_map(|(field, value)| match value {
+            Value::String(value) => Some((field, value)),
+            // optional fields that are not set are left out
+            Value::Null => None,
+            value => Some((field, value.to_string())),
+        })
+        .collect();
+    let event_name = deltas.remove("event_name").expect("domain events are tagged with event_name");
+
+    (event_name, deltas)
+}
+
+/// Reads the domain event back from the `event_name` and `deltas` of the envelope.
+pub fn decode<E: DomainEvent>(event: &Event) -> Result<E, DecodeError> {
+    if event.aggregate_type != E::AGGREGATE_TYPE {
+        return Err(DecodeError::WrongAggregateType {
+            expected: E::AGGREGATE_TYPE,
+            actual: event.aggregate_type.clone(),
+        })
+    }
+
+    let mut fields: Map<String, Value> = event.deltas
+        .iter()
+        .map(|(field, value)| (field.clone(), Value::String(value.clone())))
+        .collect();
+    fields.insert("event_name".into(), Value::String(event.event_name.clone()));
+
+    serde_json::from_value(Value::Object(fields)).map_err(|error| DecodeError::Invalid {
+        event_name: event.event_name.clone(),
+        reason: error.to_string(),
+    })
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use crate::cqrs::account::AccountEvent;
+        use crate::cqrs::account_holder::{AccountHolderChanges, AccountHolderEvent};
+        use crate::cqrs::money::{Currency, Money};
+        use crate::cqrs::upcaster::upcast;
+        use super::*;
+
+        #[test]
+        fn round_trips_through_envelope() {
+            let domain_event = AccountEvent::Debit {
+                amount: Money::new(2550, Currency::SEK),
+                transfer_id: "transfer-1".into(),
+            };
+
+            let event = Event::from_domain_event(&domain_event, HashMap::new());
+
+            assert_eq!(event.event_name, "debit");
+            assert_eq!(event.aggregate_type, "Account");
+            assert_eq!(event.deltas, HashMap::from([
+                ("amount".into(), "25.50 SEK".into()),
+                ("transfer_id".into(), "transfer-1".into()),
+            ]));
+            assert_eq!(event.decode::<AccountEvent>(), Ok(domain_event));
+        }
+
+        #[test]
+        fn leaves_out_fields_that_are_not_set() {
+            let changes = AccountHolderChanges {
+                phone_number: Some("070-123 45 67".parse().unwrap()),
+                ..Default::default()
+            };
+            let new_event = Event::new(HashMap::new(), HashMap::new(), "AccountHolder".into());
+
+            let event = new_event.update_with(&AccountHolderEvent::UpdateAccountHolderInfo(changes.clone()), HashMap::new());
+
+            assert_eq!(event.event_name, "update_account_holder_info");
+            assert_eq!(event.deltas, HashMap::from([("phone_number".into(), "+46701234567".into())]));
+            assert_eq!(event.decode(), Ok(AccountHolderEvent::UpdateAccountHolderInfo(changes)));
+        }
+
+        #[test]
+        fn decodes_events_stored_before_the_enums() {
+            let new_event = Event::new(HashMap::new(), HashMap::from([("account_holder_id".into(), "holder-1".into())]), "Account".into());
+            let mut delete_event = Event::new(HashMap::new(), HashMap::new(), "AccountHolder".into())
+                .update(HashMap::from([("deltas".into(), "deleted: true".into())]), HashMap::new(), "delete_account_holder");
+            delete_event.schema_version = 1;
+
+            assert_eq!(new_event.decode(), Ok(AccountEvent::New { account_holder_id: "holder-1".into(), currency: Currency::SEK }));
+            // as read from the event store
+            assert_eq!(upcast(delete_event).decode(), Ok(AccountHolderEvent::DeleteAccountHolder { reason: "not recorded".into() }));
+        }
+
+        #[test]
+        fn rejects_unknown_events() {
+            let event = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            let misspelled = event.update(HashMap::new(), HashMap::new(), "frezee_account");
+            let missing_amount = event.update(HashMap::new(), HashMap::new(), "deposit");
+
+            assert!(matches!(misspelled.decode::<AccountEvent>(), Err(DecodeError::Invalid { .. })));
+            assert!(matches!(missing_amount.decode::<AccountEvent>(), Err(DecodeError::Invalid { .. })));
+            assert_eq!(event.decode::<AccountHolderEvent>(), Err(DecodeError::WrongAggregateType {
+                expected: "AccountHolder",
+                actual: "Account".into(),
+            }));
+        }
+    }
@@ -2,6 +2,18 @@
 Implementation of the Event struct.
 
 Handles all the GUID, timestamps, aggregate_versions in the methods.
+The time and the ids come from a `Clock` and an `IdGenerator`. The `Event` constructors use the real ones,
+an `EventFactory` with a `FixedClock` and `SequentialIds` creates the exact same events on every run.
+
+Timestamps are UTC with microsecond precision, and serialized as RFC 3339, e.g. `2022-08-11T08:18:53.001244Z`.
+Events stored before that have timestamps like `2022-08-11 08:18:53.001244 UTC`, which are still read.
+
+The global `position` is not known when an event is created. It is assigned by the event store when the
+event is appended, and gives a gap-free total order over all events of all aggregates, starting at 1.
+The `hash` that chains the event to the one before it is set on append as well.
+
+`event_name` and `deltas` usually come from a typed domain event, see `cqrs::domain_event`.
+New events get the current `schema_version` of their deltas, older shapes are upcast on read, see `cqrs::upcaster`.
 
 Example:
 ```
@@ -12,59 +24,120 @@ Example:
 ```
 */
 
-extern crate guid_create;
 use std::collections::HashMap;
-use guid_create::GUID;
 use chrono::prelude::*;
 use rql::prelude::*;
+use crate::cqrs::clock::{Clock, SystemClock};
+use crate::cqrs::domain_event::{self, DecodeError, DomainEvent};
+use crate::cqrs::id_generator::{IdGenerator, RandomIds};
+use crate::cqrs::upcaster;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Event {
   pub aggregate_id: String,
   pub aggregate_version: u32,
   pub event_name: String,
-  pub timestamp: String, // DateTime<Utc>,
+  #[serde(with = "timestamp_format")]
+  pub timestamp: DateTime<Utc>,
   pub metadata: HashMap<String, String>,
   pub deltas: HashMap<String, String>,
-  pub aggregate_type: String
+  pub aggregate_type: String,
+  /// Global sequence number assigned by the event store on append, 0 until the event is stored.
+  #[serde(default)]
+  pub position: u64,
+  /// Version of the shape of the deltas, see `cqrs::upcaster`.
+  #[serde(default = "upcaster::first_schema_version")]
+  pub schema_version: u32,
+  /// Links the event to the one stored before it, see `database::hash_chain`. Empty until the event is stored,
+  /// and in events stored before the chain existed.
+  #[serde(default)]
+  pub hash: String,
 }
 
 impl Event {
+  /// Creates the first event of a new aggregate, with the current time and a random GUID as aggregate_id.
+  #[allow(dead_code)]
   pub fn new(metadata: HashMap<String, String>, deltas: HashMap<String, String>, aggregate_type: String) -> Event {
-    // timestamp as UTC to string
-    let timestamp: String = Utc::now().to_string();
-    // aggregate_id as a GUID
-    let aggregate_id:String = GUID::rand().to_string();
-    let aggregate_version: u32 = 1;
-    let event_name: &str = "new";
-
-    let event: Event = new_event(&aggregate_id, aggregate_version, event_name, timestamp, &metadata, deltas, &aggregate_type);
-
-    event
+    EventFactory::system().new_event(metadata, deltas, aggregate_type)
   }
   /// Creates a new event based on the last event of the same aggregate in the event store.
   /// 'update' might be little misleading name, since a completely new event is generated.
   /// The name rather suggests an update of the aggregate, not of the event itself.
+  #[allow(dead_code)]
   pub fn update(&self, changes: HashMap<String, String>, metadata: HashMap<String, String>, event_name: &str) -> Event {
-    // adds a new timestamp
-    let timestamp: String = Utc::now().to_string();
-    let aggregate_id: &String = &self.aggregate_id;
+    EventFactory::system().update(self, changes, metadata, event_name)
+  }
+  /// Creates the first event of a new aggregate from a typed domain event, see `cqrs::domain_event`.
+  pub fn from_domain_event<E: DomainEvent>(domain_event: &E, metadata: HashMap<String, String>) -> Event {
+    EventFactory::system().new_with(domain_event, metadata)
+  }
+  /// Same as `update`, with the event name and deltas taken from a typed domain event.
+  pub fn update_with<E: DomainEvent>(&self, domain_event: &E, metadata: HashMap<String, String>) -> Event {
+    EventFactory::system().update_with(self, domain_event, metadata)
+  }
+  /// Reads the typed domain event stored in this event.
+  pub fn decode<E: DomainEvent>(&self) -> Result<E, DecodeError> {
+    domain_event::decode(self)
+  }
+}
+
+/// Creates events, with the timestamps of `clock` and the aggregate ids of `ids`.
+/// The `Event` constructors use the system clock and random GUIDs, tests can pass a `FixedClock` and `SequentialIds`.
+#[derive(Clone, Copy)]
+pub struct EventFactory<'a> {
+  clock: &'a dyn Clock,
+  ids: &'a dyn IdGenerator,
+}
+
+impl<'a> EventFactory<'a> {
+  pub fn new(clock: &'a dyn Clock, ids: &'a dyn IdGenerator) -> EventFactory<'a> {
+    EventFactory { clock, ids }
+  }
+
+  /// The system clock and random GUIDs.
+  pub fn system() -> EventFactory<'static> {
+    EventFactory::new(&SystemClock, &RandomIds)
+  }
+
+  /// Creates the first event of a new aggregate.
+  pub fn new_event(&self, metadata: HashMap<String, String>, deltas: HashMap<String, String>, aggregate_type: String) -> Event {
+    let aggregate_id: String = self.ids.next_id();
+    let aggregate_version: u32 = 1;
+    let event_name: &str = "new";
+
+    new_event(&aggregate_id, aggregate_version, event_name, self.clock.now(), &metadata, deltas, &aggregate_type)
+  }
+
+  /// Creates the event following `latest_event` in the same aggregate stream.
+  pub fn update(&self, latest_event: &Event, changes: HashMap<String, String>, metadata: HashMap<String, String>, event_name: &str) -> Event {
     // increments aggregate version, very important!
-    let aggregate_version: u32 = &self.aggregate_version + 1;
-    let metadata: HashMap<String, String> = metadata;
-    let deltas: HashMap<String, String> = changes;
-    let aggregate_type: &String = &self.aggregate_type;
-    let event: Event = new_event(aggregate_id, aggregate_version, event_name, timestamp, &metadata, deltas, aggregate_type);
+    let aggregate_version: u32 = latest_event.aggregate_version + 1;
+
+    new_event(&latest_event.aggregate_id, aggregate_version, event_name, self.clock.now(), &metadata, changes, &latest_event.aggregate_type)
+  }
+
+  /// Creates the first event of a new aggregate from a typed domain event.
+  pub fn new_with<E: DomainEvent>(&self, domain_event: &E, metadata: HashMap<String, String>) -> Event {
+    let (event_name, deltas) = domain_event::encode(domain_event);
+    let mut event = self.new_event(metadata, deltas, E::AGGREGATE_TYPE.into());
+    event.event_name = event_name;
 
     event
   }
+
+  /// Creates the event following `latest_event` from a typed domain event.
+  pub fn update_with<E: DomainEvent>(&self, latest_event: &Event, domain_event: &E, metadata: HashMap<String, String>) -> Event {
+    let (event_name, deltas) = domain_event::encode(domain_event);
+
+    self.update(latest_event, deltas, metadata, &event_name)
+  }
 }
 
 /// Constructs the event
 fn new_event(aggregate_id: &String,
   aggregate_version: u32,
   event_name: &str,
-  timestamp: String, //DateTime<Utc>,
+  timestamp: DateTime<Utc>,
   metadata: &HashMap<String, String>,
   deltas: HashMap<String, String>,
   aggregate_type: &String) -> Event {
@@ -75,15 +148,47 @@ fn new_event(aggregate_id: &String,
       timestamp: timestamp,
       metadata: metadata.clone(),
       deltas: deltas,
-      aggregate_type: aggregate_type.clone()
+      aggregate_type: aggregate_type.clone(),
+      position: 0,
+      schema_version: upcaster::current_schema_version(aggregate_type, event_name),
+      hash: String::new(),
     }
   }
 
+/// Serializes timestamps as RFC 3339 with microseconds, and reads the old `Utc::to_string` format too.
+mod timestamp_format {
+  use chrono::prelude::*;
+  use serde::{Deserialize, Deserializer, Serializer};
+  use serde::de::Error;
+
+  pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&timestamp.to_rfc3339_opts(SecondsFormat::Micros, true))
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+    let timestamp = String::deserialize(deserializer)?;
+
+    parse(&timestamp).ok_or_else(|| D::Error::custom(format!("invalid timestamp: {}", timestamp)))
+  }
+
+  pub fn parse(timestamp: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(timestamp) {
+      return Some(timestamp.with_timezone(&Utc))
+    }
+
+    // e.g. "2022-08-11 08:18:53.001244 UTC"
+    NaiveDateTime::parse_from_str(timestamp.strip_suffix(" UTC")?, "%Y-%m-%d %H:%M:%S%.f")
+      .ok()
+      .map(|timestamp| timestamp.and_utc())
+  }
+}
 
 
 // cargo test -- --nocapture
 #[cfg(test)]
     mod tests {
+        use crate::cqrs::clock::FixedClock;
+        use crate::cqrs::id_generator::SequentialIds;
         use super::*;
 
         #[test]
@@ -93,13 +198,16 @@ fn new_event(aggregate_id: &String,
           let _event2: Event = create_new_event();
           let _event3: Event = create_new_event();
 
-          assert_eq!(event.timestamp.len(), 30);
+          assert!(event.timestamp <= Utc::now());
+          assert_eq!(event.timestamp.nanosecond() % 1000, 0);
           assert_eq!(event.aggregate_id.len(), 36);
           assert_eq!(event.event_name, "new");
           assert_eq!(event.metadata["a"], "1");
           assert_eq!(event.deltas["c"], "3");
           assert_eq!(event.aggregate_type, "AggregateType");
           assert_eq!(event.aggregate_version, 1);
+          assert_eq!(event.position, 0);
+          assert_eq!(event.schema_version, 1);
         }
 
         #[test]
@@ -128,6 +236,59 @@ fn new_event(aggregate_id: &String,
           assert_eq!(updated_event_2.metadata["a"], "2");
         }
 
+        #[test]
+        fn serializes_timestamp_as_rfc3339() {
+          let mut event: Event = create_new_event();
+          event.timestamp = Utc.with_ymd_and_hms(2022, 8, 11, 8, 18, 53).unwrap() + chrono::Duration::microseconds(1244);
+
+          let json = serde_json::to_value(&event).unwrap();
+          let read_back: Event = serde_json::from_value(json.clone()).unwrap();
+
+          assert_eq!(json["timestamp"], "2022-08-11T08:18:53.001244Z");
+          assert_eq!(read_back, event);
+        }
+
+        #[test]
+        fn reads_timestamps_of_old_events() {
+          let expected = Utc.with_ymd_and_hms(2022, 8, 11, 8, 18, 53).unwrap() + chrono::Duration::microseconds(1244);
+
+          assert_eq!(timestamp_format::parse("2022-08-11 08:18:53.001244 UTC"), Some(expected));
+          assert_eq!(timestamp_format::parse("2022-08-11 08:18:53.001244000 UTC"), Some(expected));
+          assert_eq!(timestamp_format::parse("2022-08-11T10:18:53.001244+02:00"), Some(expected));
+          assert_eq!(timestamp_format::parse("yesterday"), None);
+        }
+
+        #[test]
+        fn creates_the_same_events_with_fixed_clock_and_ids() {
+          let clock = FixedClock::new(Utc.with_ymd_and_hms(2022, 8, 11, 8, 18, 53).unwrap());
+          let ids = SequentialIds::new();
+          let events = EventFactory::new(&clock, &ids);
+
+          let first = events.new_event(HashMap::new(), HashMap::from([("a".into(), "1".into())]), "AggregateType".into());
+          clock.advance(chrono::Duration::minutes(5));
+          let second = events.update(&first, HashMap::from([("a".into(), "2".into())]), HashMap::new(), "update");
+          let other = events.new_event(HashMap::new(), HashMap::new(), "AggregateType".into());
+
+          assert_eq!(serde_json::to_string(&first).unwrap(), concat!(
+            r#"{"aggregate_id":"00000000-0000-0000-0000-000000000001","aggregate_version":1,"event_name":"new","#,
+            r#""timestamp":"2022-08-11T08:18:53.000000Z","metadata":{},"deltas":{"a":"1"},"#,
+            r#""aggregate_type":"AggregateType","position":0,"schema_version":1,"hash":""}"#,
+          ));
+          assert_eq!(second, Event {
+            aggregate_id: "00000000-0000-0000-0000-000000000001".into(),
+            aggregate_version: 2,
+            event_name: "update".into(),
+            timestamp: Utc.with_ymd_and_hms(2022, 8, 11, 8, 23, 53).unwrap(),
+            metadata: HashMap::new(),
+            deltas: HashMap::from([("a".into(), "2".into())]),
+            aggregate_type: "AggregateType".into(),
+            position: 0,
+            schema_version: 1,
+            hash: String::new(),
+          });
+          assert_eq!(other.aggregate_id, "00000000-0000-0000-0000-000000000002");
+        }
+
         fn create_new_event() -> Event {
           let metadata: HashMap<String, String> = HashMap::from([
               ("a".into(), "1".into()),
@@ -0,0 +1,78 @@
+/**
+Where new aggregates get their ids from.
+
+New aggregates get their `aggregate_id` from an `IdGenerator` instead of calling `GUID::rand()` directly, so
+tests can predict the ids. `RandomIds` are random GUIDs, `SequentialIds` count up from 1 in the same shape.
+
+# Example:
+```
+    let ids = SequentialIds::new();
+
+    assert_eq!(ids.next_id(), "00000000-0000-0000-0000-000000000001");
+    assert_eq!(ids.next_id(), "00000000-0000-0000-0000-000000000002");
+```
+*/
+
+extern crate guid_create;
+use std::sync::atomic::{AtomicU64, Ordering};
+use guid_create::GUID;
+
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&self) -> String;
+}
+
+/// Random GUIDs.
+pub struct RandomIds;
+
+impl IdGenerator for RandomIds {
+    fn next_id(&self) -> String {
+        GUID::rand().to_string()
+    }
+}
+
+/// GUID shaped ids counting up from 1, for tests.
+#[derive(Default)]
+pub struct SequentialIds {
+    last: AtomicU64,
+}
+
+impl SequentialIds {
+    #[allow(dead_code)]
+    pub fn new() -> SequentialIds {
+        SequentialIds::default()
+    }
+
+    /// The `n`th id handed out, counting from 1.
+    #[allow(dead_code)]
+    pub fn nth(n: u64) -> String {
+        format!("00000000-0000-0000-0000-{:012}", n)
+    }
+}
+
+impl IdGenerator for SequentialIds {
+    fn next_id(&self) -> String {
+        SequentialIds::nth(self.last.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn sequential_ids_count_up_from_one() {
+            let ids = SequentialIds::new();
+
+            assert_eq!(ids.next_id(), "00000000-0000-0000-0000-000000000001");
+            assert_eq!(ids.next_id(), "00000000-0000-0000-0000-000000000002");
+        }
+
+        #[test]
+        fn random_ids_are_guids() {
+            let ids = RandomIds;
+
+            assert_eq!(ids.next_id().len(), 36);
+            assert_ne!(ids.next_id(), ids.next_id());
+        }
+    }
@@ -1,2 +1,16 @@
 pub mod event;
-pub mod account_holder;
\ No newline at end of file
+pub mod account;
+pub mod account_holder;
+pub mod address;
+pub mod clock;
+pub mod command;
+pub mod domain_event;
+pub mod id_generator;
+pub mod money;
+pub mod personal_identity_number;
+pub mod phone_number;
+#[cfg(test)]
+pub mod scenario;
+pub mod snapshot;
+pub mod transaction;
+pub mod upcaster;
\ No newline at end of file
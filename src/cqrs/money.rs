@@ -0,0 +1,319 @@
+/**
+Fixed-point money type: an integer amount of minor units (e.g. öre) plus an ISO 4217 currency.
+
+Amounts never go through floating point. Arithmetic is checked, so adding money in different
+currencies or overflowing gives an error instead of a wrong balance.
+
+Money is formatted, serialized and stored in event deltas as the amount with all decimals of the currency
+followed by the currency code, e.g. "1234.50 SEK". Parsing that string gives back exactly the same value.
+
+# Example:
+```
+    let salary: Money = "25000.00 SEK".parse()?;
+    let rent = Money::new(850000, Currency::SEK);   // 8500.00 SEK
+
+    let left = salary.checked_sub(rent)?;
+    assert_eq!(left.to_string(), "16500.00 SEK");
+```
+*/
+
+use std::fmt;
+use std::str::FromStr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MoneyError {
+    /// Not a three letter ISO 4217 currency code.
+    InvalidCurrency(String),
+    /// The amount could not be parsed, or has more decimals than the currency allows.
+    InvalidAmount(String),
+    CurrencyMismatch(Currency, Currency),
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MoneyError::InvalidCurrency(code) => write!(f, "invalid currency code '{}'", code),
+            MoneyError::InvalidAmount(amount) => write!(f, "invalid amount '{}'", amount),
+            MoneyError::CurrencyMismatch(a, b) => write!(f, "can not combine {} with {}", a, b),
+            MoneyError::Overflow => write!(f, "amount out of range"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+/// ISO 4217 alphabetic currency code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub const SEK: Currency = Currency(*b"SEK");
+    #[allow(dead_code)]
+    pub const EUR: Currency = Currency(*b"EUR");
+    #[allow(dead_code)]
+    pub const USD: Currency = Currency(*b"USD");
+
+    pub fn new(code: &str) -> Result<Currency, MoneyError> {
+        match code.as_bytes() {
+            [a, b, c] if code.bytes().all(|letter| letter.is_ascii_uppercase()) => Ok(Currency([*a, *b, *c])),
+            _ => Err(MoneyError::InvalidCurrency(code.into())),
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("currency codes are ASCII")
+    }
+
+    /// Number of decimals of the currency, e.g. 2 for SEK (100 öre to the krona) and 0 for JPY.
+    pub fn decimals(&self) -> u32 {
+        match self.code() {
+            "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX" | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+            "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+            _ => 2,
+        }
+    }
+}
+
+/// Swedish kronor, unless stated otherwise.
+impl Default for Currency {
+    fn default() -> Currency {
+        Currency::SEK
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+impl FromStr for Currency {
+    type Err = MoneyError;
+
+    fn from_str(code: &str) -> Result<Currency, MoneyError> {
+        Currency::new(code)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Money {
+        Money { minor_units, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Money {
+        Money::new(0, currency)
+    }
+
+    #[allow(dead_code)]
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    #[allow(dead_code)]
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor_units > 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor_units < 0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor_units == 0
+    }
+
+    pub fn checked_add(&self, other: Money) -> Result<Money, MoneyError> {
+        self.check_same_currency(other)?;
+        let minor_units = self.minor_units.checked_add(other.minor_units).ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_sub(&self, other: Money) -> Result<Money, MoneyError> {
+        self.check_same_currency(other)?;
+        let minor_units = self.minor_units.checked_sub(other.minor_units).ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    pub fn checked_neg(&self) -> Result<Money, MoneyError> {
+        let minor_units = self.minor_units.checked_neg().ok_or(MoneyError::Overflow)?;
+
+        Ok(Money::new(minor_units, self.currency))
+    }
+
+    fn check_same_currency(&self, other: Money) -> Result<(), MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let decimals = self.currency.decimals();
+        let sign = if self.is_negative() { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+
+        if decimals == 0 {
+            return write!(f, "{}{} {}", sign, units, self.currency)
+        }
+
+        let divisor = 10u64.pow(decimals);
+        write!(
+            f,
+            "{}{}.{:0width$} {}",
+            sign, units / divisor, units % divisor, self.currency,
+            width = decimals as usize
+        )
+    }
+}
+
+/// Parses "<amount> <currency>", e.g. "1234.50 SEK", "-0.5 EUR" or "100 JPY".
+/// Both '.' and ',' are accepted as decimal separator.
+impl FromStr for Money {
+    type Err = MoneyError;
+
+    fn from_str(text: &str) -> Result<Money, MoneyError> {
+        let (amount, code) = text.trim()
+            .rsplit_once(' ')
+            .ok_or_else(|| MoneyError::InvalidAmount(text.into()))?;
+        let currency = Currency::new(code)?;
+        let invalid_amount = || MoneyError::InvalidAmount(text.into());
+
+        let (negative, amount) = match amount.trim().strip_prefix('-') {
+            Some(amount) => (true, amount),
+            None => (false, amount.trim()),
+        };
+        let (whole, fraction) = match amount.split_once(['.', ',']) {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (amount, ""),
+        };
+
+        let decimals = currency.decimals() as usize;
+        let all_digits = |part: &str| part.bytes().all(|digit| digit.is_ascii_digit());
+        if whole.is_empty() || !all_digits(whole) || !all_digits(fraction) || fraction.len() > decimals {
+            return Err(invalid_amount())
+        }
+
+        let whole: u64 = whole.parse().map_err(|_| invalid_amount())?;
+        let fraction: u64 = format!("{:0<width$}", fraction, width = decimals)
+            .parse()
+            .unwrap_or(0);
+
+        // the amount without sign, as i64::MIN has no positive counterpart
+        let units = whole
+            .checked_mul(10u64.pow(decimals as u32))
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or(MoneyError::Overflow)?;
+        let minor_units = if negative { 0i64.checked_sub_unsigned(units) } else { i64::try_from(units).ok() };
+
+        Ok(Money::new(minor_units.ok_or(MoneyError::Overflow)?, currency))
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Currency, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn formats_with_all_decimals_of_the_currency() {
+            assert_eq!(Money::new(123450, Currency::SEK).to_string(), "1234.50 SEK");
+            assert_eq!(Money::new(5, Currency::SEK).to_string(), "0.05 SEK");
+            assert_eq!(Money::new(-5, Currency::SEK).to_string(), "-0.05 SEK");
+            assert_eq!(Money::new(100, Currency::new("JPY").unwrap()).to_string(), "100 JPY");
+            assert_eq!(Money::new(1500, Currency::new("KWD").unwrap()).to_string(), "1.500 KWD");
+        }
+
+        #[test]
+        fn parses_amounts() {
+            assert_eq!("1234.50 SEK".parse(), Ok(Money::new(123450, Currency::SEK)));
+            assert_eq!("1234,5 SEK".parse(), Ok(Money::new(123450, Currency::SEK)));
+            assert_eq!("12 EUR".parse(), Ok(Money::new(1200, Currency::EUR)));
+            assert_eq!("-0.05 SEK".parse(), Ok(Money::new(-5, Currency::SEK)));
+            assert_eq!("100 JPY".parse(), Ok(Money::new(100, Currency::new("JPY").unwrap())));
+        }
+
+        #[test]
+        fn rejects_invalid_amounts() {
+            assert!(matches!("12.345 SEK".parse::<Money>(), Err(MoneyError::InvalidAmount(_))));
+            assert!(matches!("1.5 JPY".parse::<Money>(), Err(MoneyError::InvalidAmount(_))));
+            assert!(matches!("abc SEK".parse::<Money>(), Err(MoneyError::InvalidAmount(_))));
+            assert!(matches!(".5 SEK".parse::<Money>(), Err(MoneyError::InvalidAmount(_))));
+            assert!(matches!("100".parse::<Money>(), Err(MoneyError::InvalidAmount(_))));
+            assert!(matches!("100 kr".parse::<Money>(), Err(MoneyError::InvalidCurrency(_))));
+            assert_eq!("99999999999999999999 SEK".parse::<Money>(), Err(MoneyError::InvalidAmount("99999999999999999999 SEK".into())));
+            assert_eq!("99999999999999999 SEK".parse::<Money>(), Err(MoneyError::Overflow));
+        }
+
+        #[test]
+        fn round_trips_through_string_without_loss() {
+            for minor_units in [0, 1, -1, 99, 100, 123456789, -987654321, i64::MAX, i64::MIN + 1, i64::MIN] {
+                let money = Money::new(minor_units, Currency::SEK);
+
+                assert_eq!(money.to_string().parse(), Ok(money));
+            }
+        }
+
+        #[test]
+        fn checked_arithmetic() {
+            let a = Money::new(1000, Currency::SEK);
+            let b = Money::new(250, Currency::SEK);
+
+            assert_eq!(a.checked_add(b), Ok(Money::new(1250, Currency::SEK)));
+            assert_eq!(b.checked_sub(a), Ok(Money::new(-750, Currency::SEK)));
+            assert_eq!(a.checked_neg(), Ok(Money::new(-1000, Currency::SEK)));
+            assert_eq!(a.checked_add(Money::new(1, Currency::EUR)), Err(MoneyError::CurrencyMismatch(Currency::SEK, Currency::EUR)));
+            assert_eq!(Money::new(i64::MAX, Currency::SEK).checked_add(Money::new(1, Currency::SEK)), Err(MoneyError::Overflow));
+            assert_eq!(Money::new(i64::MIN, Currency::SEK).checked_neg(), Err(MoneyError::Overflow));
+        }
+
+        #[test]
+        fn validates_currency_codes() {
+            assert_eq!(Currency::new("SEK"), Ok(Currency::SEK));
+            assert_eq!(Currency::new("sek"), Err(MoneyError::InvalidCurrency("sek".into())));
+            assert_eq!(Currency::new("SEKK"), Err(MoneyError::InvalidCurrency("SEKK".into())));
+            assert_eq!(Currency::SEK.decimals(), 2);
+        }
+    }
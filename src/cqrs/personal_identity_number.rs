@@ -0,0 +1,250 @@
+/**
+Swedish personal identity number (personnummer), or coordination number (samordningsnummer).
+
+The number is the birth date, a three digit birth number and a check digit, e.g. "19930625-7255".
+It is parsed from the 12 digit form "YYYYMMDD-NNNC", or the 10 digit form "YYMMDD-NNNC", with or without
+the separator. In the 10 digit form the century is left out, and the separator is a "+" instead of a "-"
+once the person has turned 100. The check digit is the Luhn checksum of the 10 digit form.
+
+Coordination numbers are given to people who are not, or have not been, registered in Sweden.
+They have 60 added to the day of birth, e.g. "19701063-2391" for someone born on 1970-10-03.
+
+Numbers are formatted in the 12 digit form with a "-", which is also how they are stored in events.
+
+# Example:
+```
+    let number: PersonalIdentityNumber = "930625-7255".parse()?;
+
+    assert_eq!(number.to_string(), "19930625-7255");
+    number.check_birth_date("1993-06-25")?;
+```
+*/
+
+use std::fmt;
+use std::str::FromStr;
+use chrono::prelude::*;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PersonalIdentityNumberError {
+    /// Not 10 or 12 digits, with an optional separator before the last four.
+    InvalidFormat(String),
+    /// The number, or the date of birth it is compared with, has no valid date.
+    InvalidDate(String),
+    InvalidCheckDigit(String),
+    /// The birth date in the number is not the date of birth given with it.
+    BirthDateMismatch {
+        number: String,
+        date_of_birth: String,
+    },
+}
+
+impl fmt::Display for PersonalIdentityNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PersonalIdentityNumberError::InvalidFormat(number) => write!(f, "'{}' is not a personal identity number", number),
+            PersonalIdentityNumberError::InvalidDate(number) => write!(f, "'{}' does not have a valid date", number),
+            PersonalIdentityNumberError::InvalidCheckDigit(number) => write!(f, "'{}' has the wrong check digit", number),
+            PersonalIdentityNumberError::BirthDateMismatch { number, date_of_birth } => write!(
+                f,
+                "{} does not belong to someone born on {}",
+                number, date_of_birth
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersonalIdentityNumberError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PersonalIdentityNumber {
+    birth_date: NaiveDate,
+    coordination_number: bool,
+    birth_number: u16,
+    check_digit: u8,
+}
+
+impl PersonalIdentityNumber {
+    /// Parses the number. `today` decides the century of numbers in the 10 digit form.
+    pub fn parse(number: &str, today: NaiveDate) -> Result<PersonalIdentityNumber, PersonalIdentityNumberError> {
+        let invalid_format = || PersonalIdentityNumberError::InvalidFormat(number.into());
+        if !number.is_ascii() {
+            return Err(invalid_format())
+        }
+
+        let (digits, separator) = match number.len().checked_sub(5).map(|index| number.split_at(index)) {
+            Some((date, last_four)) if last_four.starts_with(['-', '+']) => (format!("{}{}", date, &last_four[1..]), &last_four[..1]),
+            _ => (number.to_string(), ""),
+        };
+        if !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return Err(invalid_format())
+        }
+        let digit = |index: usize| u32::from(digits.as_bytes()[index] - b'0');
+        let number_at = |from: usize, to: usize| digits[from..to].parse::<u32>().unwrap();
+
+        // the last 10 digits, and the year if it is written out
+        let (short_form, year) = match (digits.len(), separator) {
+            (12, "" | "-") => (&digits[2..], Some(number_at(0, 4) as i32)),
+            (10, _) => (&digits[..], None),
+            _ => return Err(invalid_format()),
+        };
+        let offset = digits.len() - 10;
+
+        if luhn_check_digit(&short_form[..9]) != digit(offset + 9) {
+            return Err(PersonalIdentityNumberError::InvalidCheckDigit(number.into()))
+        }
+
+        let month = number_at(offset + 2, offset + 4);
+        let written_day = number_at(offset + 4, offset + 6);
+        let coordination_number = written_day > 60;
+        let day = if coordination_number { written_day - 60 } else { written_day };
+
+        let year = year.unwrap_or_else(|| {
+            // the latest year the person can have been born in, 100 years earlier with a "+"
+            let mut year = today.year() / 100 * 100 + number_at(0, 2) as i32;
+            if (year, month, day) > (today.year(), today.month(), today.day()) {
+                year -= 100;
+            }
+            if separator == "+" {
+                year -= 100;
+            }
+            year
+        });
+
+        let birth_date = NaiveDate::from_ymd_opt(year, month, day)
+            .filter(|birth_date| *birth_date <= today)
+            .ok_or_else(|| PersonalIdentityNumberError::InvalidDate(number.into()))?;
+
+        Ok(PersonalIdentityNumber {
+            birth_date,
+            coordination_number,
+            birth_number: number_at(offset + 6, offset + 9) as u16,
+            check_digit: digit(offset + 9) as u8,
+        })
+    }
+
+    pub fn birth_date(&self) -> NaiveDate {
+        self.birth_date
+    }
+
+    #[allow(dead_code)]
+    pub fn is_coordination_number(&self) -> bool {
+        self.coordination_number
+    }
+
+    /// Checks that the number belongs to someone born on `date_of_birth`, given as "YYYY-MM-DD" or "YYYYMMDD".
+    pub fn check_birth_date(&self, date_of_birth: &str) -> Result<(), PersonalIdentityNumberError> {
+        let parsed = parse_date_of_birth(date_of_birth)?;
+
+        if parsed != self.birth_date {
+            return Err(PersonalIdentityNumberError::BirthDateMismatch {
+                number: self.to_string(),
+                date_of_birth: date_of_birth.into(),
+            })
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a date of birth given as "YYYY-MM-DD" or "YYYYMMDD".
+pub fn parse_date_of_birth(date_of_birth: &str) -> Result<NaiveDate, PersonalIdentityNumberError> {
+    NaiveDate::parse_from_str(date_of_birth, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date_of_birth, "%Y%m%d"))
+        .map_err(|_| PersonalIdentityNumberError::InvalidDate(date_of_birth.into()))
+}
+
+/// The Luhn check digit of the digits: every other digit from the first is doubled, and the digits of
+/// the products and the remaining digits are summed up.
+fn luhn_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .bytes()
+        .map(|digit| u32::from(digit - b'0'))
+        .enumerate()
+        .map(|(index, digit)| if index % 2 == 0 { digit * 2 } else { digit })
+        .map(|product| product / 10 + product % 10)
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+impl fmt::Display for PersonalIdentityNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let written_day = if self.coordination_number { self.birth_date.day() + 60 } else { self.birth_date.day() };
+
+        write!(
+            f,
+            "{:04}{:02}{:02}-{:03}{}",
+            self.birth_date.year(), self.birth_date.month(), written_day, self.birth_number, self.check_digit
+        )
+    }
+}
+
+/// Parses the number as of today.
+impl FromStr for PersonalIdentityNumber {
+    type Err = PersonalIdentityNumberError;
+
+    fn from_str(number: &str) -> Result<PersonalIdentityNumber, PersonalIdentityNumberError> {
+        PersonalIdentityNumber::parse(number, Utc::now().date_naive())
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_all_forms() {
+            for number in ["19930625-7255", "199306257255", "930625-7255", "9306257255"] {
+                let parsed = parse(number).unwrap();
+
+                assert_eq!(parsed.to_string(), "19930625-7255");
+                assert_eq!(parsed.birth_date(), NaiveDate::from_ymd_opt(1993, 6, 25).unwrap());
+                assert!(!parsed.is_coordination_number());
+            }
+        }
+
+        #[test]
+        fn picks_century_of_short_form() {
+            assert_eq!(parse("121212-1212").unwrap().to_string(), "20121212-1212");
+            assert_eq!(parse("121212+1212").unwrap().to_string(), "19121212-1212");
+            // not born yet this century, so born last century
+            assert_eq!(parse("221228-9876").unwrap().birth_date().year(), 1922);
+            assert_eq!(parse("221228+9876").unwrap().birth_date().year(), 1822);
+        }
+
+        #[test]
+        fn parses_coordination_numbers() {
+            let parsed = parse("701063-2391").unwrap();
+
+            assert!(parsed.is_coordination_number());
+            assert_eq!(parsed.birth_date(), NaiveDate::from_ymd_opt(1970, 10, 3).unwrap());
+            assert_eq!(parsed.to_string(), "19701063-2391");
+        }
+
+        #[test]
+        fn rejects_invalid_numbers() {
+            assert_eq!(parse("930625-7256"), Err(PersonalIdentityNumberError::InvalidCheckDigit("930625-7256".into())));
+            assert_eq!(parse("930230-7252"), Err(PersonalIdentityNumberError::InvalidDate("930230-7252".into())));
+            for number in ["", "930625", "9306257-255", "930625–7255", "93O625-7255", "19930625+7255", "1993062572555"] {
+                assert_eq!(parse(number), Err(PersonalIdentityNumberError::InvalidFormat(number.into())));
+            }
+        }
+
+        #[test]
+        fn checks_birth_date() {
+            let number = parse("19930625-7255").unwrap();
+
+            assert_eq!(number.check_birth_date("1993-06-25"), Ok(()));
+            assert_eq!(number.check_birth_date("19930625"), Ok(()));
+            assert_eq!(number.check_birth_date("1993-06-26"), Err(PersonalIdentityNumberError::BirthDateMismatch {
+                number: "19930625-7255".into(),
+                date_of_birth: "1993-06-26".into(),
+            }));
+            assert_eq!(number.check_birth_date("25/6 1993"), Err(PersonalIdentityNumberError::InvalidDate("25/6 1993".into())));
+        }
+
+        fn parse(number: &str) -> Result<PersonalIdentityNumber, PersonalIdentityNumberError> {
+            PersonalIdentityNumber::parse(number, NaiveDate::from_ymd_opt(2022, 8, 11).unwrap())
+        }
+    }
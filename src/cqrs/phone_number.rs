@@ -0,0 +1,129 @@
+/**
+Phone number in the international E.164 format, e.g. "+46763154177".
+
+Numbers are parsed from the ways they are usually written in Sweden: national numbers with a leading 0,
+e.g. "0763-154177" or "076-315 41 77", and international numbers with "+" or "00" in front of the country code,
+e.g. "+46 (0)76-315 41 77" or "0046763154177". Spaces, dashes, dots, slashes and parentheses are ignored, and
+national numbers are Swedish.
+
+Numbers are formatted, serialized and stored in event deltas in the E.164 format. Stored numbers are read back
+as they are: they were parsed when the event was created, or kept as written if they were stored before numbers
+were parsed and could not be, see `cqrs::upcaster::StructureContactDetails`.
+
+# Example:
+```
+    let number: PhoneNumber = "076-315 41 77".parse()?;
+
+    assert_eq!(number.to_string(), "+46763154177");
+```
+*/
+
+use std::fmt;
+use std::str::FromStr;
+use rql::prelude::*;
+
+static SWEDISH_COUNTRY_CODE: &str = "46";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhoneNumberError {
+    /// Not a national number starting with 0, or an international number starting with "+" or "00".
+    InvalidFormat(String),
+    /// Too few or too many digits for a number in the country.
+    InvalidLength(String),
+}
+
+impl fmt::Display for PhoneNumberError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhoneNumberError::InvalidFormat(number) => write!(f, "'{}' is not a phone number", number),
+            PhoneNumberError::InvalidLength(number) => write!(f, "'{}' has the wrong number of digits", number),
+        }
+    }
+}
+
+impl std::error::Error for PhoneNumberError {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+pub struct PhoneNumber(String);
+
+impl PhoneNumber {
+    pub fn parse(number: &str) -> Result<PhoneNumber, PhoneNumberError> {
+        let invalid_format = || PhoneNumberError::InvalidFormat(number.into());
+
+        // the trunk prefix in "+46 (0)76..." is not dialed from abroad
+        let digits: String = number
+            .replacen("(0)", "", 1)
+            .chars()
+            .filter(|char| !matches!(char, ' ' | '-' | '.' | '/' | '(' | ')'))
+            .collect();
+
+        let international = match (digits.strip_prefix('+'), digits.strip_prefix("00"), digits.strip_prefix('0')) {
+            (Some(international), _, _) | (None, Some(international), _) => international.to_string(),
+            (None, None, Some(national)) => format!("{}{}", SWEDISH_COUNTRY_CODE, national),
+            _ => return Err(invalid_format()),
+        };
+        if international.is_empty() || !international.bytes().all(|digit| digit.is_ascii_digit()) || international.starts_with('0') {
+            return Err(invalid_format())
+        }
+
+        let valid_length = match international.strip_prefix(SWEDISH_COUNTRY_CODE) {
+            Some(national) => (7..=9).contains(&national.len()) && !national.starts_with('0'),
+            None => (7..=15).contains(&international.len()),
+        };
+        if !valid_length {
+            return Err(PhoneNumberError::InvalidLength(number.into()))
+        }
+
+        Ok(PhoneNumber(format!("+{}", international)))
+    }
+
+    #[allow(dead_code)]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for PhoneNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for PhoneNumber {
+    type Err = PhoneNumberError;
+
+    fn from_str(number: &str) -> Result<PhoneNumber, PhoneNumberError> {
+        PhoneNumber::parse(number)
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parses_swedish_formats() {
+            for number in ["0763-154177", "076-315 41 77", "0763154177", "+46 76 315 41 77", "+46 (0)76-315 41 77", "0046763154177"] {
+                assert_eq!(PhoneNumber::parse(number).unwrap().to_string(), "+46763154177");
+            }
+            assert_eq!(PhoneNumber::parse("046-12 34 56").unwrap().to_string(), "+4646123456");
+        }
+
+        #[test]
+        fn parses_international_numbers() {
+            assert_eq!(PhoneNumber::parse("+1 (212) 555-0100").unwrap().to_string(), "+12125550100");
+            assert_eq!(PhoneNumber::parse("0047 22 12 34 56").unwrap().to_string(), "+4722123456");
+        }
+
+        #[test]
+        fn rejects_invalid_numbers() {
+            for number in ["", "763154177", "+", "0763-15417x", "+046763154177"] {
+                assert_eq!(PhoneNumber::parse(number), Err(PhoneNumberError::InvalidFormat(number.into())));
+            }
+            for number in ["012345", "0763-154177123", "+4612", "+1234567890123456"] {
+                assert_eq!(PhoneNumber::parse(number), Err(PhoneNumberError::InvalidLength(number.into())));
+            }
+        }
+    }
@@ -0,0 +1,151 @@
+/**
+Given-When-Then fixture for testing commands against an in-memory event store.
+
+A scenario stores the events that already happened (given), runs a single command against the store (when),
+and checks the events the command appended, or the error it returned (then). Every scenario has its own
+`InMemoryEventStore`, so domain tests do not touch the database on disk and can run in parallel.
+
+Each `given` starts a new aggregate. The given events get their aggregate ids from `SequentialIds`, so the first
+aggregate has the id `id(1)`, the second `id(2)` and so on, and their timestamps from a `FixedClock`.
+
+# Example:
+```
+    given(&[AccountHolderEvent::New { .. }])
+        .given(&[AccountEvent::New { account_holder_id: id(1), currency: Currency::SEK }])
+        .when(|store| withdraw(store, &id(2), sek(100)))
+        .then_error(AccountError::InsufficientFunds { .. });
+```
+*/
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use chrono::prelude::*;
+use crate::cqrs::clock::FixedClock;
+use crate::cqrs::domain_event::DomainEvent;
+use crate::cqrs::event::{Event, EventFactory};
+use crate::cqrs::id_generator::SequentialIds;
+use crate::database::event_store::EventStore;
+use crate::database::in_memory_event_store::InMemoryEventStore;
+
+pub struct Scenario {
+    store: InMemoryEventStore,
+    clock: FixedClock,
+    ids: SequentialIds,
+}
+
+/// What the command of a scenario did.
+pub struct Outcome<T, Err> {
+    result: Result<T, Err>,
+    appended: Vec<Event>,
+}
+
+/// The id of the `n`th aggregate given in a scenario, counting from 1.
+pub fn id(n: u64) -> String {
+    SequentialIds::nth(n)
+}
+
+/// Starts a scenario with the events of one aggregate.
+pub fn given<E: DomainEvent>(events: &[E]) -> Scenario {
+    Scenario::new().given(events)
+}
+
+impl Scenario {
+    /// A scenario where nothing has happened yet.
+    pub fn new() -> Scenario {
+        Scenario {
+            store: InMemoryEventStore::new(),
+            clock: FixedClock::new(Utc.with_ymd_and_hms(2022, 8, 11, 8, 0, 0).unwrap()),
+            ids: SequentialIds::new(),
+        }
+    }
+
+    /// Stores the events of another aggregate, the first event is the `new` event.
+    pub fn given<E: DomainEvent>(self, events: &[E]) -> Scenario {
+        let factory = EventFactory::new(&self.clock, &self.ids);
+        let mut latest_event: Option<Event> = None;
+
+        for domain_event in events {
+            let event = match &latest_event {
+                Some(latest_event) => factory.update_with(latest_event, domain_event, HashMap::new()),
+                None => factory.new_with(domain_event, HashMap::new()),
+            };
+            self.store.append(event.clone(), event.aggregate_version - 1).unwrap();
+            latest_event = Some(event);
+        }
+
+        self
+    }
+
+    /// Runs the command, and records the events it appended to the store.
+    pub fn when<T, Err>(self, command: impl FnOnce(&dyn EventStore) -> Result<T, Err>) -> Outcome<T, Err> {
+        let last_position = self.store.last_position();
+
+        let result = command(&self.store);
+
+        Outcome {
+            result,
+            appended: self.store.read_all(last_position + 1),
+        }
+    }
+}
+
+impl<T, Err: Debug> Outcome<T, Err> {
+    /// Asserts that the command succeeded and appended exactly the expected events, in order.
+    /// Returns what the command returned, for further checks.
+    pub fn then<E: DomainEvent + Debug + PartialEq>(self, expected: &[E]) -> T {
+        let appended: Vec<E> = self.appended
+            .iter()
+            .map(|event| event.decode().unwrap())
+            .collect();
+
+        assert_eq!(appended, expected);
+
+        self.result.unwrap()
+    }
+
+    /// Asserts that the command failed with the expected error, and did not append anything.
+    pub fn then_error(self, expected: Err) where Err: PartialEq {
+        assert_eq!(self.result.err(), Some(expected));
+        assert_eq!(self.appended, vec![]);
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use crate::cqrs::account::{deposit, AccountError, AccountEvent};
+        use crate::cqrs::money::{Currency, Money};
+        use super::*;
+
+        #[test]
+        fn given_aggregates_get_sequential_ids() {
+            let store = given(&[AccountEvent::New { account_holder_id: "holder".into(), currency: Currency::SEK }])
+                .given(&[
+                    AccountEvent::New { account_holder_id: "holder".into(), currency: Currency::SEK },
+                    AccountEvent::FreezeAccount,
+                ])
+                .store;
+
+            assert_eq!(store.latest_version(&id(1), "Account"), 1);
+            assert_eq!(store.latest_version(&id(2), "Account"), 2);
+            assert_eq!(id(2), "00000000-0000-0000-0000-000000000002");
+        }
+
+        #[test]
+        fn then_checks_appended_events() {
+            let amount = Money::new(100, Currency::SEK);
+
+            let event = given(&[AccountEvent::New { account_holder_id: "holder".into(), currency: Currency::SEK }])
+                .when(|store| deposit(store, &id(1), amount))
+                .then(&[AccountEvent::Deposit { amount }]);
+
+            assert_eq!(event.aggregate_version, 2);
+        }
+
+        #[test]
+        fn then_error_checks_error() {
+            Scenario::new()
+                .when(|store| deposit(store, &id(1), Money::new(100, Currency::SEK)))
+                .then_error(AccountError::AccountNotFound(id(1)));
+        }
+    }
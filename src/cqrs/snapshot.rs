@@ -0,0 +1,188 @@
+/**
+Snapshots of aggregate state, so long streams do not have to be replayed from the first event.
+
+A `Snapshot` is the state of one aggregate serialized at a given `aggregate_version`, stored in the event store
+next to the event log. `rehydrate` loads the newest snapshot of the aggregate and only applies the events after it.
+Whenever it had to apply `SNAPSHOT_INTERVAL` events or more, it stores a new snapshot, so busy streams get a
+snapshot about every `SNAPSHOT_INTERVAL` events and short streams never need one.
+
+Snapshots are only a cache: the events stay the source of truth. A snapshot that can not be deserialized any more,
+e.g. because the state struct changed, is ignored and the state is rebuilt from all events.
+
+# Example:
+```
+    let (account, latest_event) = rehydrate::<Account>(&store, &aggregate_id)
+        .ok_or(AccountError::AccountNotFound(aggregate_id.into()))?;
+```
+*/
+
+use rql::prelude::*;
+use serde::de::DeserializeOwned;
+use crate::cqrs::event::Event;
+use crate::database::event_store::EventStore;
+
+/// Number of replayed events after which a new snapshot is taken.
+pub const SNAPSHOT_INTERVAL: u32 = 100;
+
+/// State of an aggregate that can be rebuilt from its events, and stored in a snapshot.
+pub trait Snapshotable: serde::Serialize + DeserializeOwned {
+    /// The `aggregate_type` of the stream the state is built from.
+    const AGGREGATE_TYPE: &'static str;
+
+    /// Builds the state from the events of one aggregate, ordered by aggregate_version.
+    fn from_events(events: &[Event]) -> Option<Self>;
+
+    /// Applies a single event on top of the current state.
+    fn apply(&mut self, event: &Event);
+
+    /// The version of the last event applied.
+    fn aggregate_version(&self) -> u32;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub aggregate_id: String,
+    pub aggregate_type: String,
+    /// The version of the last event included in the state.
+    pub aggregate_version: u32,
+    /// The state, serialized as JSON.
+    pub state: String,
+}
+
+impl Snapshot {
+    pub fn new<S: Snapshotable>(aggregate_id: &str, state: &S) -> Snapshot {
+        Snapshot {
+            aggregate_id: aggregate_id.into(),
+            aggregate_type: S::AGGREGATE_TYPE.into(),
+            aggregate_version: state.aggregate_version(),
+            state: serde_json::to_string(state).expect("aggregate state serializes to JSON"),
+        }
+    }
+}
+
+/// Rebuilds the state of the aggregate from its newest snapshot and the events after it.
+///
+/// Returns the state together with the latest event of the stream, or None if the aggregate has no events.
+pub fn rehydrate<S: Snapshotable>(store: &dyn EventStore, aggregate_id: &str) -> Option<(S, Event)> {
+    let snapshot_state = store.latest_snapshot(aggregate_id, S::AGGREGATE_TYPE)
+        .and_then(|snapshot| serde_json::from_str::<S>(&snapshot.state).ok());
+
+    let (state, events) = match snapshot_state {
+        Some(mut state) => {
+            let events = store.read_stream_from(aggregate_id, S::AGGREGATE_TYPE, state.aggregate_version() + 1);
+            for event in &events {
+                state.apply(event);
+            }
+            (state, events)
+        },
+        None => {
+            let events = store.read_stream(aggregate_id, S::AGGREGATE_TYPE);
+            (S::from_events(&events)?, events)
+        },
+    };
+
+    let latest_event = match events.last() {
+        Some(event) => event.clone(),
+        None => store.last_event(aggregate_id, S::AGGREGATE_TYPE)?,
+    };
+
+    if events.len() as u32 >= SNAPSHOT_INTERVAL {
+        store.save_snapshot(Snapshot::new(aggregate_id, &state));
+    }
+
+    Some((state, latest_event))
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use crate::cqrs::account::{deposit, open_account};
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::cqrs::money::{Currency, Money};
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::projections::account::Account;
+        use super::*;
+
+        #[test]
+        fn short_streams_are_not_snapshotted() {
+            let store = InMemoryEventStore::new();
+            let account_id = open_test_account(&store, 3);
+
+            let (account, latest_event) = rehydrate::<Account>(&store, &account_id).unwrap();
+
+            assert_eq!(account.aggregate_version, 4);
+            assert_eq!(latest_event.aggregate_version, 4);
+            assert!(store.latest_snapshot(&account_id, "Account").is_none());
+        }
+
+        #[test]
+        fn snapshots_long_streams_on_rehydration() {
+            let store = InMemoryEventStore::new();
+            // the commands have replayed at most SNAPSHOT_INTERVAL - 1 events so far
+            let account_id = open_test_account(&store, SNAPSHOT_INTERVAL - 1);
+            assert!(store.latest_snapshot(&account_id, "Account").is_none());
+
+            let (account, _) = rehydrate::<Account>(&store, &account_id).unwrap();
+            let snapshot = store.latest_snapshot(&account_id, "Account").unwrap();
+
+            assert_eq!(snapshot.aggregate_version, SNAPSHOT_INTERVAL);
+            assert_eq!(serde_json::from_str::<Account>(&snapshot.state).unwrap(), account);
+        }
+
+        #[test]
+        fn replays_only_events_after_the_snapshot() {
+            let store = InMemoryEventStore::new();
+            let account_id = open_test_account(&store, SNAPSHOT_INTERVAL);
+            rehydrate::<Account>(&store, &account_id).unwrap();
+            deposit(&store, &account_id, Money::new(100, Currency::SEK)).unwrap();
+
+            let (account, latest_event) = rehydrate::<Account>(&store, &account_id).unwrap();
+            let replayed = Account::from_events(&store.read_stream(&account_id, "Account")).unwrap();
+
+            assert_eq!(account, replayed);
+            assert_eq!(account.balance, Money::new(100 * (SNAPSHOT_INTERVAL as i64 + 1), Currency::SEK));
+            assert_eq!(latest_event.aggregate_version, SNAPSHOT_INTERVAL + 2);
+        }
+
+        #[test]
+        fn ignores_snapshots_that_can_not_be_read() {
+            let store = InMemoryEventStore::new();
+            let account_id = open_test_account(&store, 2);
+            store.save_snapshot(Snapshot {
+                aggregate_id: account_id.clone(),
+                aggregate_type: "Account".into(),
+                aggregate_version: 2,
+                state: "not json".into(),
+            });
+
+            let (account, _) = rehydrate::<Account>(&store, &account_id).unwrap();
+
+            assert_eq!(account.aggregate_version, 3);
+            assert_eq!(account.balance, Money::new(200, Currency::SEK));
+        }
+
+        #[test]
+        fn unknown_aggregate_has_no_state() {
+            let store = InMemoryEventStore::new();
+
+            assert!(rehydrate::<Account>(&store, "unknown aggregate id").is_none());
+        }
+
+        // opens an account and deposits 1.00 SEK `deposits` times
+        fn open_test_account(store: &InMemoryEventStore, deposits: u32) -> String {
+            let holder = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            store.append(holder.clone(), 0).unwrap();
+            let account_id = open_account(store, &holder.aggregate_id, Currency::SEK).unwrap().aggregate_id;
+            for _ in 0..deposits {
+                deposit(store, &account_id, Money::new(100, Currency::SEK)).unwrap();
+            }
+
+            account_id
+        }
+    }
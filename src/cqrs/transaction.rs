@@ -1,9 +1,380 @@
+/**
+Transfers of money between two Accounts, coordinated by a process manager.
+
+A transfer is an aggregate of its own, of type "Transfer", whose stream records how far it has come:
+
+```text
+    transfer_initiated -> debited -> credited -> transfer_completed
+            |                |
+            |                +-> transfer_failed   the destination could not be credited, the debit is reversed first
+            +-> transfer_failed                    the source account could not be debited
+```
+
+The money itself is moved by `debit`, `credit` and `reverse_debit` events on the two Account streams,
+each carrying the id of the transfer. Every step is a separate append, so the process can stop after any of them.
+`process_transfer` picks up wherever the transfer stream ends: before taking a step it looks in the account stream
+whether the step was already taken, and then only records it on the transfer stream. Money is therefore never
+debited or credited twice, and a failed transfer is only recorded once the debit has been given back.
+Between the debit and the credit the money is in transit, on the transfer.
+
+`resume_pending_transfers` finishes every transfer that was interrupted, e.g. by a crash, and is run on startup.
+
+# Example:
+```
+    let store = RqlEventStore::open_default()?;
+
+    let transfer = transfer(&store, &from_account_id, &to_account_id, "100.00 SEK".parse()?)?;
+    assert_eq!(transfer.status, TransferStatus::Completed);
+
+    // after a crash
+    let finished = resume_pending_transfers(&store);
+```
+*/
+
+use std::collections::HashMap;
+use rql::prelude::*;
+use thiserror::Error;
+use crate::cqrs::account::{self, AccountError, AccountEvent};
+use crate::cqrs::domain_event::DomainEvent;
+use crate::cqrs::event::*;
+use crate::cqrs::money::{Money, MoneyError};
+use crate::database::event_store::{AppendError, EventStore};
+use crate::projections::transfer::{Transfer, TransferStatus};
+
+#[allow(dead_code)]
+static AGGREGATE_TYPE: &str = "Transfer";
+
+/// Everything that can happen to a Transfer, stored as `event_name` and `deltas` of the `Event`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event_name", rename_all = "snake_case")]
+pub enum TransferEvent {
+    TransferInitiated {
+        from_account_id: String,
+        to_account_id: String,
+        amount: Money,
+    },
+    Debited,
+    Credited,
+    TransferCompleted,
+    TransferFailed {
+        reason: String,
+    },
+}
+
+impl DomainEvent for TransferEvent {
+    const AGGREGATE_TYPE: &'static str = "Transfer";
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TransactionError {
+    /// Transfers must be of a positive amount.
+    #[error("invalid amount {0}")]
+    InvalidAmount(Money),
+    /// The source and destination of a transfer are the same account.
+    #[error("can not transfer from account {0} to itself")]
+    SameAccount(String),
+    #[error("transfer {0} not found")]
+    TransferNotFound(String),
+    /// E.g. an amount in another currency than the accounts.
+    #[error("{0}")]
+    Money(#[from] MoneyError),
+    #[error("{0}")]
+    Account(#[source] AccountError),
+    #[error("{0}")]
+    Append(#[from] AppendError),
+}
+
+impl From<AccountError> for TransactionError {
+    fn from(error: AccountError) -> TransactionError {
+        match error {
+            AccountError::Append(error) => TransactionError::Append(error),
+            error => TransactionError::Account(error),
+        }
+    }
+}
+
+/// Moves `amount` from one account to another, and returns the finished transfer.
+/// A transfer the accounts do not allow, e.g. because of insufficient funds, is returned with status Failed.
+#[allow(dead_code)]
+pub fn transfer(store: &dyn EventStore, from_account_id: &str, to_account_id: &str, amount: Money) -> Result<Transfer, TransactionError> {
+    let initiated = initiate_transfer(store, from_account_id, to_account_id, amount)?;
+
+    process_transfer(store, &initiated.aggregate_id)
+}
+
+/// Records the intent to transfer `amount` between two existing accounts in the same currency, without moving any money yet.
+pub fn initiate_transfer(store: &dyn EventStore, from_account_id: &str, to_account_id: &str, amount: Money) -> Result<Event, TransactionError> {
+    if !amount.is_positive() {
+        return Err(TransactionError::InvalidAmount(amount))
+    }
+    if from_account_id == to_account_id {
+        return Err(TransactionError::SameAccount(from_account_id.into()))
+    }
+    for account_id in [from_account_id, to_account_id] {
+        let (account, _) = account::load_account(store, account_id)?;
+        // fails on a currency mismatch
+        account.balance.checked_add(amount)?;
+    }
+
+    let domain_event = TransferEvent::TransferInitiated {
+        from_account_id: from_account_id.into(),
+        to_account_id: to_account_id.into(),
+        amount,
+    };
+    let event = Event::from_domain_event(&domain_event, HashMap::new());
+
+    store.append(event.clone(), 0)?;
+
+    Ok(event)
+}
+
+/// Takes the remaining steps of the transfer, until it is completed or failed.
+///
+/// Safe to call again after it was interrupted: steps already taken on the account streams are not taken twice.
+/// A conflicting append on one of the streams stops the process with an error, and leaves the transfer
+/// pending until it is processed again.
+pub fn process_transfer(store: &dyn EventStore, aggregate_id: &str) -> Result<Transfer, TransactionError> {
+    loop {
+        let (transfer, latest_event) = load_transfer(store, aggregate_id)?;
+
+        let domain_event = match transfer.status {
+            TransferStatus::Initiated => debit_source(store, &transfer)?,
+            TransferStatus::Debited => credit_destination(store, &transfer)?,
+            TransferStatus::Credited => TransferEvent::TransferCompleted,
+            TransferStatus::Completed | TransferStatus::Failed => return Ok(transfer),
+        };
+
+        let event = latest_event.update_with(&domain_event, HashMap::new());
+        store.append(event, latest_event.aggregate_version)?;
+    }
+}
+
+/// Processes every transfer that is neither completed nor failed, e.g. after a crash in the middle of a transfer.
+/// Returns the outcome per transfer.
+#[allow(dead_code)]
+pub fn resume_pending_transfers(store: &dyn EventStore) -> Vec<Result<Transfer, TransactionError>> {
+    store.read_all(1)
+        .iter()
+        .filter(|event| matches!(event.decode::<TransferEvent>(), Ok(TransferEvent::TransferInitiated { .. })))
+        .filter(|event| {
+            let transfer = Transfer::from_events(&store.read_stream(&event.aggregate_id, AGGREGATE_TYPE));
+            transfer.is_some_and(|transfer| !transfer.is_finished())
+        })
+        .map(|event| process_transfer(store, &event.aggregate_id))
+        .collect()
+}
+
+/// Rebuilds the transfer from its event stream.
+pub fn load_transfer(store: &dyn EventStore, aggregate_id: &str) -> Result<(Transfer, Event), TransactionError> {
+    let events = store.read_stream(aggregate_id, AGGREGATE_TYPE);
+
+    match (Transfer::from_events(&events), events.last()) {
+        (Some(transfer), Some(latest_event)) => Ok((transfer, latest_event.clone())),
+        _ => Err(TransactionError::TransferNotFound(aggregate_id.into())),
+    }
+}
+
+fn debit_source(store: &dyn EventStore, transfer: &Transfer) -> Result<TransferEvent, TransactionError> {
+    let already_debited = find_account_event(store, &transfer.from_account_id, |event| {
+        matches!(event, AccountEvent::Debit { transfer_id, .. } if *transfer_id == transfer.aggregate_id)
+    });
+    if already_debited.is_some() {
+        return Ok(TransferEvent::Debited)
+    }
+
+    match account::debit(store, &transfer.from_account_id, transfer.amount, &transfer.aggregate_id) {
+        Ok(_) => Ok(TransferEvent::Debited),
+        Err(AccountError::Append(error)) => Err(error.into()),
+        Err(error) => Ok(TransferEvent::TransferFailed { reason: error.to_string() }),
+    }
+}
+
+fn credit_destination(store: &dyn EventStore, transfer: &Transfer) -> Result<TransferEvent, TransactionError> {
+    let already_credited = find_account_event(store, &transfer.to_account_id, |event| {
+        matches!(event, AccountEvent::Credit { transfer_id, .. } if *transfer_id == transfer.aggregate_id)
+    });
+    if already_credited.is_some() {
+        return Ok(TransferEvent::Credited)
+    }
+    // the credit was rejected before, and the money is back on the source account
+    let reversal = find_account_event(store, &transfer.from_account_id, |event| {
+        matches!(event, AccountEvent::ReverseDebit { transfer_id, .. } if *transfer_id == transfer.aggregate_id)
+    });
+    if let Some(AccountEvent::ReverseDebit { reason, .. }) = reversal {
+        return Ok(TransferEvent::TransferFailed { reason })
+    }
+
+    match account::credit(store, &transfer.to_account_id, transfer.amount, &transfer.aggregate_id) {
+        Ok(_) => Ok(TransferEvent::Credited),
+        Err(AccountError::Append(error)) => Err(error.into()),
+        Err(error) => {
+            let reason = error.to_string();
+            account::reverse_debit(store, &transfer.from_account_id, transfer.amount, &transfer.aggregate_id, &reason)?;
+
+            Ok(TransferEvent::TransferFailed { reason })
+        }
+    }
+}
+
+/// Finds the first event of the account that matches, e.g. the debit on behalf of a transfer.
+fn find_account_event(store: &dyn EventStore, account_id: &str, matches: impl Fn(&AccountEvent) -> bool) -> Option<AccountEvent> {
+    store.read_stream(account_id, AccountEvent::AGGREGATE_TYPE)
+        .iter()
+        .filter_map(|event| event.decode::<AccountEvent>().ok())
+        .find(|event| matches(event))
+}
+
+// cargo test -- --nocapture
 #[cfg(test)]
     mod tests {
+        use crate::cqrs::account::{close_account, deposit, load_account, open_account};
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::cqrs::money::Currency;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
         use super::*;
 
         #[test]
-        fn internal() {
-            assert_eq!(4, internal_adder(2, 2));
+        fn transfers_money_between_accounts() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(10000));
+            let to_account_id = store_new_account(&store, sek(0));
+
+            let transfer = transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+
+            assert_eq!(transfer.status, TransferStatus::Completed);
+            assert_eq!(transfer.amount, sek(2500));
+            assert_eq!(event_names(&store, &transfer.aggregate_id), vec!["transfer_initiated", "debited", "credited", "transfer_completed"]);
+            assert_eq!(balance(&store, &from_account_id), sek(7500));
+            assert_eq!(balance(&store, &to_account_id), sek(2500));
+        }
+
+        #[test]
+        fn fails_without_moving_money_when_source_can_not_be_debited() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(1000));
+            let to_account_id = store_new_account(&store, sek(0));
+
+            let transfer = transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+
+            assert_eq!(transfer.status, TransferStatus::Failed);
+            assert!(transfer.failure_reason.starts_with("can not withdraw"));
+            assert_eq!(event_names(&store, &transfer.aggregate_id), vec!["transfer_initiated", "transfer_failed"]);
+            assert_eq!(balance(&store, &from_account_id), sek(1000));
+            assert_eq!(balance(&store, &to_account_id), sek(0));
+        }
+
+        #[test]
+        fn reverses_debit_when_destination_can_not_be_credited() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(10000));
+            let to_account_id = store_new_account(&store, sek(0));
+            let initiated = initiate_transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+            close_account(&store, &to_account_id).unwrap();
+
+            let transfer = process_transfer(&store, &initiated.aggregate_id).unwrap();
+
+            assert_eq!(transfer.status, TransferStatus::Failed);
+            assert_eq!(event_names(&store, &transfer.aggregate_id), vec!["transfer_initiated", "debited", "transfer_failed"]);
+            assert!(find_account_event(&store, &from_account_id, |event| matches!(event, AccountEvent::ReverseDebit { .. })).is_some());
+            assert_eq!(balance(&store, &from_account_id), sek(10000));
+            assert_eq!(balance(&store, &to_account_id), sek(0));
+        }
+
+        #[test]
+        fn resumes_transfer_interrupted_after_debit_without_debiting_twice() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(10000));
+            let to_account_id = store_new_account(&store, sek(0));
+            let initiated = initiate_transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+            // crash after the account was debited, but before the transfer recorded it
+            account::debit(&store, &from_account_id, sek(2500), &initiated.aggregate_id).unwrap();
+
+            let resumed = resume_pending_transfers(&store);
+
+            assert_eq!(resumed.len(), 1);
+            assert_eq!(resumed[0].as_ref().unwrap().status, TransferStatus::Completed);
+            assert_eq!(balance(&store, &from_account_id), sek(7500));
+            assert_eq!(balance(&store, &to_account_id), sek(2500));
+        }
+
+        #[test]
+        fn resumes_transfer_interrupted_after_reversal_without_crediting() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(10000));
+            let to_account_id = store_new_account(&store, sek(0));
+            let initiated = initiate_transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+            account::debit(&store, &from_account_id, sek(2500), &initiated.aggregate_id).unwrap();
+            store.append(initiated.update_with(&TransferEvent::Debited, HashMap::new()), 1).unwrap();
+            // crash after the debit was reversed, but before the transfer recorded that it failed
+            account::reverse_debit(&store, &from_account_id, sek(2500), &initiated.aggregate_id, "account closed").unwrap();
+
+            let transfer = process_transfer(&store, &initiated.aggregate_id).unwrap();
+
+            assert_eq!(transfer.status, TransferStatus::Failed);
+            assert_eq!(transfer.failure_reason, "account closed");
+            assert_eq!(balance(&store, &from_account_id), sek(10000));
+            assert_eq!(balance(&store, &to_account_id), sek(0));
+        }
+
+        #[test]
+        fn finished_transfers_are_not_resumed() {
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store, sek(10000));
+            let to_account_id = store_new_account(&store, sek(0));
+            transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+            transfer(&store, &from_account_id, &to_account_id, sek(99999)).unwrap();
+
+            assert!(resume_pending_transfers(&store).is_empty());
+        }
+
+        #[test]
+        fn rejects_invalid_transfers() {
+            let store = InMemoryEventStore::new();
+            let account_id = store_new_account(&store, sek(10000));
+            let other_account_id = store_new_account(&store, sek(0));
+
+            assert_eq!(transfer(&store, &account_id, &other_account_id, sek(0)), Err(TransactionError::InvalidAmount(sek(0))));
+            assert_eq!(transfer(&store, &account_id, &account_id, sek(100)), Err(TransactionError::SameAccount(account_id.clone())));
+            assert_eq!(
+                transfer(&store, &account_id, "unknown", sek(100)),
+                Err(TransactionError::Account(AccountError::AccountNotFound("unknown".into())))
+            );
+            assert_eq!(
+                transfer(&store, &account_id, &other_account_id, Money::new(100, Currency::EUR)),
+                Err(TransactionError::Money(MoneyError::CurrencyMismatch(Currency::SEK, Currency::EUR)))
+            );
+            assert_eq!(process_transfer(&store, "unknown"), Err(TransactionError::TransferNotFound("unknown".into())));
+        }
+
+        fn sek(minor_units: i64) -> Money {
+            Money::new(minor_units, Currency::SEK)
+        }
+
+        fn balance(store: &dyn EventStore, account_id: &str) -> Money {
+            load_account(store, account_id).unwrap().0.balance
+        }
+
+        fn event_names(store: &dyn EventStore, transfer_id: &str) -> Vec<String> {
+            store.read_stream(transfer_id, "Transfer")
+                .into_iter()
+                .map(|event| event.event_name)
+                .collect()
+        }
+
+        fn store_new_account(store: &dyn EventStore, initial_deposit: Money) -> String {
+            let account_holder = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            store.append(account_holder.clone(), 0).unwrap();
+            let account_id = open_account(store, &account_holder.aggregate_id, Currency::SEK).unwrap().aggregate_id;
+            if initial_deposit.is_positive() {
+                deposit(store, &account_id, initial_deposit).unwrap();
+            }
+
+            account_id
         }
-    }
\ No newline at end of file
+    }
@@ -0,0 +1,280 @@
+/**
+Upcasters bring events stored in an old shape up to date when they are read from the event store.
+
+Events are stored forever and never rewritten, but the shape of their `deltas` changes as the domain evolves.
+Every event carries the `schema_version` of its shape, per aggregate type and event name, starting at 1.
+When the shape of an event changes, add an `Upcaster` that turns deltas of the old version into the next
+version and register it in `UPCASTERS`. New events are then stored with the next version, and the event stores
+run every event they read through `upcast`, which applies the upcasters in order until the event is current.
+The rest of the code only ever sees events in the current shape.
+
+Upcasters only change the deltas, the event name stays the same.
+The upcasters are kept here, next to `UPCASTERS`, and not with their aggregates: they describe the shapes
+events used to have, and only depend on the value objects they parse, so the aggregates never depend back on
+this module.
+
+# Example:
+```
+    // AccountHolder "new" events got an `email` field in version 2
+    struct AddEmail;
+
+    impl Upcaster for AddEmail {
+        fn aggregate_type(&self) -> &str { "AccountHolder" }
+        fn event_name(&self) -> &str { "new" }
+        fn source_version(&self) -> u32 { 1 }
+        fn upcast(&self, deltas: &mut HashMap<String, String>) {
+            deltas.insert("email".into(), String::new());
+        }
+    }
+
+    static UPCASTERS: &[&(dyn Upcaster + Sync)] = &[&DropDeletedFlag, &AddEmail];
+```
+*/
+
+use std::collections::HashMap;
+use crate::cqrs::address::Address;
+use crate::cqrs::event::Event;
+use crate::cqrs::phone_number::PhoneNumber;
+
+pub trait Upcaster {
+    fn aggregate_type(&self) -> &str;
+
+    fn event_name(&self) -> &str;
+
+    /// The schema version of the events this upcaster reads. It turns them into version `source_version() + 1`.
+    fn source_version(&self) -> u32;
+
+    fn upcast(&self, deltas: &mut HashMap<String, String>);
+}
+
+/// Every registered upcaster, for all aggregate types and events.
+static UPCASTERS: &[&(dyn Upcaster + Sync)] = &[
+    &DropDeletedFlag,
+    &AddDeletionReason,
+    &StructureContactDetails("new"),
+    &StructureContactDetails("update_account_holder_info"),
+];
+
+/// Schema version of events stored before events had a version.
+pub fn first_schema_version() -> u32 {
+    1
+}
+
+/// The version new events with the given aggregate type and name are stored with.
+pub fn current_schema_version(aggregate_type: &str, event_name: &str) -> u32 {
+    let mut version = first_schema_version();
+    while find_upcaster(aggregate_type, event_name, version).is_some() {
+        version += 1;
+    }
+
+    version
+}
+
+/// Brings the event up to its current schema version.
+pub fn upcast(mut event: Event) -> Event {
+    while let Some(upcaster) = find_upcaster(&event.aggregate_type, &event.event_name, event.schema_version) {
+        upcaster.upcast(&mut event.deltas);
+        event.schema_version += 1;
+    }
+
+    event
+}
+
+fn find_upcaster(aggregate_type: &str, event_name: &str, version: u32) -> Option<&'static (dyn Upcaster + Sync)> {
+    UPCASTERS
+        .iter()
+        .find(|upcaster| {
+            upcaster.aggregate_type() == aggregate_type
+                && upcaster.event_name() == event_name
+                && upcaster.source_version() == version
+        })
+        .copied()
+}
+
+/// Delete events used to carry a meaningless `deltas: "deleted: true"` entry. Version 2 has no deltas.
+pub struct DropDeletedFlag;
+
+impl Upcaster for DropDeletedFlag {
+    fn aggregate_type(&self) -> &str {
+        "AccountHolder"
+    }
+
+    fn event_name(&self) -> &str {
+        "delete_account_holder"
+    }
+
+    fn source_version(&self) -> u32 {
+        1
+    }
+
+    fn upcast(&self, deltas: &mut HashMap<String, String>) {
+        deltas.remove("deltas");
+    }
+}
+
+/// Version 2 of delete events had no reason. Version 3 has one, which is "not recorded" for older deletions.
+pub struct AddDeletionReason;
+
+impl Upcaster for AddDeletionReason {
+    fn aggregate_type(&self) -> &str {
+        "AccountHolder"
+    }
+
+    fn event_name(&self) -> &str {
+        "delete_account_holder"
+    }
+
+    fn source_version(&self) -> u32 {
+        2
+    }
+
+    fn upcast(&self, deltas: &mut HashMap<String, String>) {
+        deltas.insert("reason".into(), "not recorded".into());
+    }
+}
+
+/// Version 1 of `new` and `update_account_holder_info` events had the phone number and home address as written,
+/// e.g. "0763-154177" and "Nöbbelövs Torg 37, 22652 LUND, Sweden". Version 2 has them parsed, the address in
+/// separate deltas. Anything that can not be parsed is kept as written, the address as the street.
+pub struct StructureContactDetails(pub &'static str);
+
+impl Upcaster for StructureContactDetails {
+    fn aggregate_type(&self) -> &str {
+        "AccountHolder"
+    }
+
+    fn event_name(&self) -> &str {
+        self.0
+    }
+
+    fn source_version(&self) -> u32 {
+        1
+    }
+
+    fn upcast(&self, deltas: &mut HashMap<String, String>) {
+        if let Some(phone_number) = deltas.get_mut("phone_number") {
+            if let Ok(parsed) = PhoneNumber::parse(phone_number) {
+                *phone_number = parsed.to_string();
+            }
+        }
+
+        if let Some(home_address) = deltas.remove("home_address") {
+            let address = Address::parse(&home_address).ok();
+            let street = address.as_ref().map_or(home_address, |address| address.street().into());
+            let part = |part: fn(&Address) -> &str| address.as_ref().map_or(String::new(), |address| part(address).into());
+
+            deltas.extend([
+                ("postal_code".into(), part(Address::postal_code)),
+                ("city".into(), part(Address::city)),
+                ("country_code".into(), part(Address::country_code)),
+                ("street".into(), street),
+            ]);
+        }
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use crate::cqrs::account_holder::{create_new_account_holder, delete_account_holder_by_id};
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use super::*;
+
+        #[test]
+        fn new_events_get_the_current_version() {
+            let event = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            let store = InMemoryEventStore::new();
+            store.append(event.clone(), 0).unwrap();
+
+            let delete_event = delete_account_holder_by_id(&store, event.aggregate_id.clone(), "requested by the account holder").unwrap();
+
+            assert_eq!(event.schema_version, 2);
+            assert_eq!(current_schema_version("AccountHolder", "new"), 2);
+            assert_eq!(delete_event.schema_version, 3);
+            assert_eq!(current_schema_version("AccountHolder", "delete_account_holder"), 3);
+        }
+
+        #[test]
+        fn upcasts_old_events_on_read() {
+            let store = InMemoryEventStore::new();
+            let new_event = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            // as stored before delete events had a schema version
+            let mut old_delete_event = new_event.update(
+                HashMap::from([("deltas".into(), "deleted: true".into())]),
+                HashMap::new(),
+                "delete_account_holder",
+            );
+            old_delete_event.schema_version = 1;
+            store.append(new_event.clone(), 0).unwrap();
+            store.append(old_delete_event, 1).unwrap();
+
+            let read_event = store.last_event(&new_event.aggregate_id, "AccountHolder").unwrap();
+
+            assert_eq!(read_event.schema_version, 3);
+            assert_eq!(read_event.deltas, HashMap::from([("reason".into(), "not recorded".into())]));
+            assert_eq!(store.read_all(1)[1], read_event);
+            assert_eq!(store.read_stream(&new_event.aggregate_id, "AccountHolder")[1], read_event);
+        }
+
+        #[test]
+        fn structures_contact_details_of_old_events() {
+            let mut new_event = Event::new(HashMap::new(), HashMap::from([
+                ("full_name".into(), "Isak Törnros".into()),
+                ("phone_number".into(), "0763-154177".into()),
+                ("home_address".into(), "Nöbbelövs Torg 37, 22652 LUND, Sweden".into()),
+            ]), "AccountHolder".into());
+            new_event.schema_version = 1;
+            let mut update_event = new_event.update(
+                HashMap::from([("home_address".into(), "somewhere in Lund".into())]),
+                HashMap::new(),
+                "update_account_holder_info",
+            );
+            update_event.schema_version = 1;
+
+            let new_event = upcast(new_event);
+            let update_event = upcast(update_event);
+
+            assert_eq!(new_event.schema_version, 2);
+            assert_eq!(new_event.deltas, HashMap::from([
+                ("full_name".into(), "Isak Törnros".into()),
+                ("phone_number".into(), "+46763154177".into()),
+                ("street".into(), "Nöbbelövs Torg 37".into()),
+                ("postal_code".into(), "226 52".into()),
+                ("city".into(), "LUND".into()),
+                ("country_code".into(), "SE".into()),
+            ]));
+            // kept as written, nothing is lost
+            assert_eq!(update_event.deltas, HashMap::from([
+                ("street".into(), "somewhere in Lund".into()),
+                ("postal_code".into(), "".into()),
+                ("city".into(), "".into()),
+                ("country_code".into(), "".into()),
+            ]));
+        }
+
+        #[test]
+        fn current_events_are_left_alone() {
+            let event = create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+
+            assert_eq!(upcast(event.clone()), event);
+        }
+    }
@@ -0,0 +1,445 @@
+/**
+`EventStore` that encrypts personal data before it reaches the event log, so it can be forgotten later on.
+
+The store wraps another event store. The deltas listed in `PERSONAL_DATA` for the type of the aggregate are
+encrypted with AES-256-GCM, with a key of the aggregate from the `KeyStore`, before the event is appended, and
+decrypted again when the event is read. Snapshots of those aggregates are encrypted as a whole. Everything else,
+i.e. the event envelope, event names and other deltas, is stored as before.
+
+Forgetting the personal data of an aggregate means deleting its key: the events stay in the event log unchanged,
+but their encrypted deltas read as `ERASED` from then on, and its snapshots are ignored.
+
+Events stored before personal data was encrypted hold it in plaintext, and deleting a key does not erase it.
+`encrypt_stored_personal_data` is the one-off migration that encrypts it with the key of the aggregate, and
+deletes the snapshots that are not encrypted, see `rusty-bank migrate`. Those snapshots are never read anyway.
+Until the migration has run, `ForgetAccountHolder` refuses AccountHolders with personal data in plaintext, see
+`has_plaintext_personal_data`. Events in the hash chain can not be rewritten, so personal data appended in
+plaintext after the chain existed, i.e. through the plain event store, can not be encrypted; the migration
+reports those events.
+
+Encrypted deltas are stored as "encrypted:" followed by the hex encoded nonce and ciphertext. The aggregate id
+and the name of the delta are authenticated with it, so an encrypted value can not be moved to another delta
+or aggregate. Upcasters run in the wrapped store, before decryption, so they can move or drop encrypted deltas
+but not read them.
+
+# Example:
+```
+    let events = RqlEventStore::open_default()?;
+    let keys = RqlKeyStore::open_default();
+    let store = EncryptedEventStore::new(&events, &keys);
+
+    let event = create_new_account_holder(full_name, ssn, date_of_birth, phone_number, home_address)?;
+    store.append(event, 0)?;
+    let events = store.read_stream(&aggregate_id, "AccountHolder");   // decrypted
+```
+*/
+
+use std::fmt;
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chrono::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::snapshot::Snapshot;
+use crate::cqrs::upcaster::upcast;
+use crate::database::event_store::*;
+use crate::database::hash_chain::BrokenLink;
+use crate::database::hex::{from_hex_vec, to_hex};
+use crate::database::key_store::{EncryptionKey, KeyStore};
+use crate::database::rql_event_store::RqlEventStore;
+
+/// The deltas with personal data, per aggregate type. They are kept here, and not with their aggregates, so the
+/// aggregates can check for personal data in plaintext without this module depending back on them.
+static PERSONAL_DATA: &[(&str, &[&str])] = &[
+    ("AccountHolder", &["full_name", "social_security_number", "date_of_birth", "phone_number", "street", "postal_code", "city"]),
+];
+
+/// What encrypted deltas read as once the key of the aggregate is deleted.
+pub static ERASED: &str = "[erased]";
+
+static ENCRYPTED_PREFIX: &str = "encrypted:";
+
+/// Length of an AES-GCM nonce in bytes.
+static NONCE_LENGTH: usize = 12;
+
+pub struct EncryptedEventStore<'a> {
+    events: &'a dyn EventStore,
+    keys: &'a dyn KeyStore,
+}
+
+impl<'a> EncryptedEventStore<'a> {
+    #[allow(dead_code)]
+    pub fn new(events: &'a dyn EventStore, keys: &'a dyn KeyStore) -> EncryptedEventStore<'a> {
+        EncryptedEventStore { events, keys }
+    }
+
+    /// Returns the key of the aggregate, and creates it if the aggregate has none yet.
+    fn key_for(&self, aggregate_id: &str) -> EncryptionKey {
+        self.keys.key(aggregate_id).unwrap_or_else(|| {
+            let mut key = EncryptionKey::default();
+            key.copy_from_slice(&Aes256Gcm::generate_key(OsRng));
+
+            self.keys.get_or_insert_key(aggregate_id, key)
+        })
+    }
+
+    fn encrypt_event(&self, mut event: Event) -> Event {
+        let fields = personal_data(&event.aggregate_type);
+        if !event.deltas.keys().any(|field| fields.contains(&field.as_str())) {
+            return event
+        }
+
+        let key = self.key_for(&event.aggregate_id);
+        for (field, value) in event.deltas.iter_mut().filter(|(field, _)| fields.contains(&field.as_str())) {
+            *value = encrypt(&key, &associated_data(&event.aggregate_id, field), value);
+        }
+
+        event
+    }
+
+    fn decrypt_event(&self, mut event: Event) -> Event {
+        if !event.deltas.values().any(|value| value.starts_with(ENCRYPTED_PREFIX)) {
+            return event
+        }
+
+        let key = self.keys.key(&event.aggregate_id);
+        for (field, value) in event.deltas.iter_mut().filter(|(_, value)| value.starts_with(ENCRYPTED_PREFIX)) {
+            *value = key
+                .and_then(|key| decrypt(&key, &associated_data(&event.aggregate_id, field), value))
+                .unwrap_or_else(|| ERASED.into());
+        }
+
+        event
+    }
+
+    fn decrypt_events(&self, events: Vec<Event>) -> Vec<Event> {
+        events.into_iter().map(|event| self.decrypt_event(event)).collect()
+    }
+}
+
+/// Outcome of `encrypt_stored_personal_data`, printed by the `migrate` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptionReport {
+    /// Events whose personal data was encrypted.
+    pub encrypted: usize,
+    /// Positions of the events in the hash chain that hold personal data in plaintext. They can not be rewritten,
+    /// so their AccountHolders can not be forgotten.
+    pub skipped: Vec<u64>,
+}
+
+impl fmt::Display for EncryptionReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "encrypted the personal data of {} events", self.encrypted)?;
+        if !self.skipped.is_empty() {
+            let positions: Vec<String> = self.skipped.iter().map(|position| position.to_string()).collect();
+            write!(
+                f,
+                ", {} events in the hash chain hold personal data in plaintext and can not be encrypted, at positions {}",
+                self.skipped.len(), positions.join(", ")
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// One-off migration for the events stored before personal data was encrypted: encrypts their personal data with
+/// the key of the aggregate, so forgetting the aggregate erases it. The events are upcast first, as upcasters can
+/// not read encrypted deltas. Also deletes the snapshots of aggregates with personal data that are not encrypted.
+///
+/// Events in the hash chain are not rewritten, that would break the chain. Those that hold personal data in
+/// plaintext, e.g. appended through the plain `RqlEventStore`, are reported as skipped.
+pub fn encrypt_stored_personal_data(events: &RqlEventStore, keys: &dyn KeyStore) -> Result<EncryptionReport, BrokenLink> {
+    let store = EncryptedEventStore::new(events, keys);
+
+    let encrypted = events.rewrite_unchained_events(|event| {
+        if !has_plaintext_personal_data(event) {
+            return false
+        }
+
+        let mut current = upcast(event.clone());
+        let fields = personal_data(&current.aggregate_type);
+        let key = store.key_for(&current.aggregate_id);
+        for (field, value) in current.deltas.iter_mut().filter(|(field, value)| fields.contains(&field.as_str()) && !value.starts_with(ENCRYPTED_PREFIX)) {
+            *value = encrypt(&key, &associated_data(&current.aggregate_id, field), value);
+        }
+        *event = current;
+
+        true
+    })?;
+    events.delete_snapshots(|snapshot| !personal_data(&snapshot.aggregate_type).is_empty() && !snapshot.state.starts_with(ENCRYPTED_PREFIX))?;
+    let skipped = events.read_all_as_stored(1)
+        .iter()
+        .filter(|event| has_plaintext_personal_data(event))
+        .map(|event| event.position)
+        .collect();
+
+    Ok(EncryptionReport { encrypted, skipped })
+}
+
+/// Whether the event, as stored, holds personal data in plaintext, i.e. it was stored before personal data was
+/// encrypted or without an `EncryptedEventStore`. Deleting the key of the aggregate does not erase that data.
+/// The event is checked in its current shape, so personal data in deltas that upcasters restructure is found too.
+pub fn has_plaintext_personal_data(event: &Event) -> bool {
+    let fields = personal_data(&event.aggregate_type);
+    if fields.is_empty() {
+        return false
+    }
+
+    upcast(event.clone())
+        .deltas
+        .iter()
+        .any(|(field, value)| fields.contains(&field.as_str()) && !value.is_empty() && !value.starts_with(ENCRYPTED_PREFIX))
+}
+
+impl EventStore for EncryptedEventStore<'_> {
+    fn append(&self, event: Event, expected_version: u32) -> Result<u64, AppendError> {
+        self.events.append(self.encrypt_event(event), expected_version)
+    }
+
+    fn read_stream_from(&self, aggregate_id: &str, aggregate_type: &str, from_version: u32) -> Vec<Event> {
+        self.decrypt_events(self.events.read_stream_from(aggregate_id, aggregate_type, from_version))
+    }
+
+    fn last_event(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Event> {
+        self.events.last_event(aggregate_id, aggregate_type).map(|event| self.decrypt_event(event))
+    }
+
+    fn read_all(&self, from_position: u64) -> Vec<Event> {
+        self.decrypt_events(self.events.read_all(from_position))
+    }
+
+    /// The hash chain is over the encrypted events, so it stays intact when personal data is forgotten.
+    fn read_all_as_stored(&self, from_position: u64) -> Vec<Event> {
+        self.events.read_all_as_stored(from_position)
+    }
+
+    fn read_stream_as_stored(&self, aggregate_id: &str, aggregate_type: &str) -> Vec<Event> {
+        self.events.read_stream_as_stored(aggregate_id, aggregate_type)
+    }
+
+    fn read_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+        self.decrypt_events(self.events.read_between(from, to))
+    }
+
+    fn last_position(&self) -> u64 {
+        self.events.last_position()
+    }
+
+    fn latest_version(&self, aggregate_id: &str, aggregate_type: &str) -> u32 {
+        self.events.latest_version(aggregate_id, aggregate_type)
+    }
+
+    fn save_snapshot(&self, mut snapshot: Snapshot) {
+        if !personal_data(&snapshot.aggregate_type).is_empty() {
+            let key = self.key_for(&snapshot.aggregate_id);
+            snapshot.state = encrypt(&key, &associated_data(&snapshot.aggregate_id, "snapshot"), &snapshot.state);
+        }
+
+        self.events.save_snapshot(snapshot)
+    }
+
+    /// Snapshots that can not be decrypted any more are left out, like snapshots that can not be deserialized.
+    /// So are snapshots of aggregates with personal data that were taken before it was encrypted.
+    fn latest_snapshot(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Snapshot> {
+        let mut snapshot = self.events.latest_snapshot(aggregate_id, aggregate_type)?;
+        if !personal_data(&snapshot.aggregate_type).is_empty() {
+            let key = self.keys.key(aggregate_id)?;
+            snapshot.state = decrypt(&key, &associated_data(aggregate_id, "snapshot"), &snapshot.state)?;
+        }
+
+        Some(snapshot)
+    }
+}
+
+/// The deltas with personal data of events of the aggregate type.
+fn personal_data(aggregate_type: &str) -> &'static [&'static str] {
+    PERSONAL_DATA
+        .iter()
+        .find(|(personal_data_type, _)| *personal_data_type == aggregate_type)
+        .map_or(&[], |(_, fields)| *fields)
+}
+
+/// Ties an encrypted value to the delta, or snapshot, of the aggregate it belongs to.
+fn associated_data(aggregate_id: &str, field: &str) -> String {
+    format!("{}/{}", aggregate_id, field)
+}
+
+fn encrypt(key: &EncryptionKey, associated_data: &str, plaintext: &str) -> String {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, Payload { msg: plaintext.as_bytes(), aad: associated_data.as_bytes() })
+        .expect("AES-GCM encrypts any plaintext that fits in memory");
+
+    format!("{}{}{}", ENCRYPTED_PREFIX, to_hex(&nonce), to_hex(&ciphertext))
+}
+
+/// Returns None if the value is not encrypted with the key for the associated data.
+fn decrypt(key: &EncryptionKey, associated_data: &str, encrypted: &str) -> Option<String> {
+    let bytes = from_hex_vec(encrypted.strip_prefix(ENCRYPTED_PREFIX)?)?;
+    if bytes.len() < NONCE_LENGTH {
+        return None
+    }
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LENGTH);
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: associated_data.as_bytes() })
+        .ok()?;
+
+    String::from_utf8(plaintext).ok()
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::cqrs::snapshot::{rehydrate, Snapshot};
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::key_store::InMemoryKeyStore;
+        use crate::projections::account_holder::AccountHolder;
+        use super::*;
+
+        #[test]
+        fn encrypts_personal_data_at_rest() {
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let event = new_account_holder_event();
+
+            store.append(event.clone(), 0).unwrap();
+
+            let stored = events.last_event(&event.aggregate_id, "AccountHolder").unwrap();
+            for field in personal_data("AccountHolder") {
+                assert!(stored.deltas[*field].starts_with(ENCRYPTED_PREFIX), "{} is not encrypted", field);
+                assert!(!stored.deltas[*field].contains(&event.deltas[*field]));
+            }
+            assert_eq!(stored.deltas["country_code"], "SE");
+            assert!(keys.key(&event.aggregate_id).is_some());
+
+            assert_eq!(store.last_event(&event.aggregate_id, "AccountHolder").unwrap().deltas, event.deltas);
+            assert_eq!(store.read_all(1), store.read_stream(&event.aggregate_id, "AccountHolder"));
+        }
+
+        #[test]
+        fn deleted_key_erases_personal_data() {
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let event = new_account_holder_event();
+            store.append(event.clone(), 0).unwrap();
+
+            keys.delete_key(&event.aggregate_id);
+
+            let read = store.last_event(&event.aggregate_id, "AccountHolder").unwrap();
+            assert_eq!(read.deltas["full_name"], ERASED);
+            assert_eq!(read.deltas["social_security_number"], ERASED);
+            assert_eq!(read.deltas["country_code"], "SE");
+            let account_holder = AccountHolder::from_events(&[read]).unwrap();
+            assert_eq!(account_holder.full_name, ERASED);
+        }
+
+        #[test]
+        fn leaves_other_events_alone() {
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let account_event = Event::new(HashMap::new(), HashMap::from([("account_holder_id".into(), "holder-1".into())]), "Account".into());
+
+            store.append(account_event.clone(), 0).unwrap();
+
+            let stored = events.last_event(&account_event.aggregate_id, "Account").unwrap();
+            assert_eq!(stored.deltas, account_event.deltas);
+            assert!(!has_plaintext_personal_data(&stored));
+            assert_eq!(keys.key(&account_event.aggregate_id), None);
+        }
+
+        #[test]
+        fn encrypts_personal_data_stored_before_encryption() {
+            let dir = "test_database_plaintext_personal_data";
+            let _ = std::fs::remove_dir_all(dir);
+            // stored before personal data was encrypted, and before the hash chain
+            let mut plain_event = new_account_holder_event();
+            plain_event.schema_version = 1;
+            plain_event.deltas.remove("street");
+            plain_event.deltas.insert("home_address".into(), "Nöbbelövs Torg 37, 22652 LUND, Sweden".into());
+            {
+                let events = RqlEventStore::open(dir).unwrap();
+                events.schema.event_mut().insert(Event { position: 1, ..plain_event.clone() });
+                events.schema.snapshot_mut().insert(Snapshot {
+                    aggregate_id: plain_event.aggregate_id.clone(),
+                    aggregate_type: "AccountHolder".into(),
+                    aggregate_version: 1,
+                    state: "{\"full_name\":\"Isak Törnros\"}".into(),
+                });
+            }
+            let (events, keys) = (RqlEventStore::open(dir).unwrap(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let plain_stored = events.read_stream_as_stored(&plain_event.aggregate_id, "AccountHolder").remove(0);
+            assert!(has_plaintext_personal_data(&plain_stored));
+            assert_eq!(store.latest_snapshot(&plain_event.aggregate_id, "AccountHolder"), None);
+
+            let report = encrypt_stored_personal_data(&events, &keys).unwrap();
+
+            let stored = events.read_stream_as_stored(&plain_event.aggregate_id, "AccountHolder").remove(0);
+            assert_eq!(report, EncryptionReport { encrypted: 1, skipped: vec![] });
+            assert!(!has_plaintext_personal_data(&stored));
+            assert!(stored.deltas.values().all(|value| !value.contains("Törnros") && !value.contains("Nöbbelövs")));
+            assert_eq!(stored.position, 1);
+            assert_eq!(events.schema.snapshot().rows().count(), 0);
+            assert_eq!(store.last_event(&plain_event.aggregate_id, "AccountHolder").unwrap(), upcast(plain_stored));
+            assert_eq!(encrypt_stored_personal_data(&RqlEventStore::open(dir).unwrap(), &keys).map(|report| report.encrypted), Ok(0));
+            // appended in plaintext through the plain store, so in the hash chain
+            let chained_plain = events.append(new_account_holder_event(), 0).unwrap();
+            assert_eq!(
+                encrypt_stored_personal_data(&events, &keys),
+                Ok(EncryptionReport { encrypted: 0, skipped: vec![chained_plain] })
+            );
+
+            keys.delete_key(&plain_event.aggregate_id);
+
+            let read = store.last_event(&plain_event.aggregate_id, "AccountHolder").unwrap();
+            assert_eq!(read.deltas["full_name"], ERASED);
+            assert_eq!(read.deltas["street"], ERASED);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn encrypted_values_can_not_be_moved() {
+            let key = [7; 32];
+            let encrypted = encrypt(&key, "holder-1/full_name", "Isak Törnros");
+
+            assert_eq!(decrypt(&key, "holder-1/full_name", &encrypted), Some("Isak Törnros".into()));
+            assert_eq!(decrypt(&key, "holder-1/city", &encrypted), None);
+            assert_eq!(decrypt(&key, "holder-2/full_name", &encrypted), None);
+            assert_eq!(decrypt(&[8; 32], "holder-1/full_name", &encrypted), None);
+            assert_eq!(decrypt(&key, "holder-1/full_name", "encrypted:00ff"), None);
+        }
+
+        #[test]
+        fn encrypts_snapshots_of_personal_data() {
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let event = new_account_holder_event();
+            store.append(event.clone(), 0).unwrap();
+            let (account_holder, _) = rehydrate::<AccountHolder>(&store, &event.aggregate_id).unwrap();
+
+            store.save_snapshot(Snapshot::new(&event.aggregate_id, &account_holder));
+
+            let stored = events.latest_snapshot(&event.aggregate_id, "AccountHolder").unwrap();
+            assert!(stored.state.starts_with(ENCRYPTED_PREFIX));
+            assert_eq!(store.latest_snapshot(&event.aggregate_id, "AccountHolder").unwrap().state, serde_json::to_string(&account_holder).unwrap());
+
+            keys.delete_key(&event.aggregate_id);
+
+            assert_eq!(store.latest_snapshot(&event.aggregate_id, "AccountHolder"), None);
+        }
+
+        fn new_account_holder_event() -> Event {
+            create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap()
+        }
+    }
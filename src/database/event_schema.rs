@@ -1,15 +1,46 @@
+use std::io::ErrorKind;
+use std::path::Path;
 use rql::prelude::*;
 use rql::mashup;
+use serde::de::DeserializeOwned;
 use crate::cqrs::event::*;
+use crate::cqrs::snapshot::Snapshot;
 
 schema! {
   pub EventSchema {
     event: Event,
+    snapshot: Snapshot,
   }
 }
 
+impl EventSchema {
+    /// Reloads the tables from disk, like `reload`, but fails if a table file can not be read or parsed.
+    /// `reload` loads an empty table then, which the next write saves over the whole file.
+    /// The tables are left as they were when it fails.
+    pub fn try_reload(&self) -> rql::Result<()> {
+        let event = load_table(self.params.for_table_guard("event").path, self.params.repr)?;
+        let snapshot = load_table(self.params.for_table_guard("snapshot").path, self.params.repr)?;
+
+        *self.event.write().expect("Thread using table panicked") = event;
+        *self.snapshot.write().expect("Thread using table panicked") = snapshot;
+
+        Ok(())
+    }
+}
+
+/// Loads the table from its file, a table that has no file yet is empty.
+fn load_table<T: DeserializeOwned, P: AsRef<Path>>(path: P, repr: Representation) -> rql::Result<Table<T>> {
+    match Table::load(path, repr) {
+        Err(rql::Error::Io(error)) if error.kind() == ErrorKind::NotFound => Ok(Table::new()),
+        result => result,
+    }
+}
+
+/// The database directory of the event log.
+pub static DEFAULT_DIR: &str = "test_database_example";
+
 pub fn get_schema() -> EventSchema {
-    let schema = EventSchema::new("test_database_example", HumanReadable).unwrap();
+    let schema = EventSchema::new(DEFAULT_DIR, HumanReadable).unwrap();
 
     return schema
 }
@@ -19,6 +50,7 @@ pub fn get_schema() -> EventSchema {
     mod tests {
         // use std::collections::HashMap;
         use crate::database;
+        use crate::database::rql_event_store::RqlEventStore;
         use super::*;
 
         // #[test]
@@ -50,7 +82,7 @@ pub fn get_schema() -> EventSchema {
         #[serial_test::serial]
         fn read_events() {
             let db = setup();
-            let table = db.event();
+            let table = db.schema.event();
 
             let event_names: Vec<String> =
                 table
@@ -71,7 +103,7 @@ pub fn get_schema() -> EventSchema {
             assert_eq!(event_names.len(), 8);
         }
 
-        fn setup() -> EventSchema{
+        fn setup() -> RqlEventStore{
             database::ruql::setup()
         }
 
@@ -0,0 +1,435 @@
+/**
+The event store abstraction, i.e. everything the domain code needs from the event log.
+
+Domain code only talks to the `EventStore` trait, so the backing storage can be swapped without touching it:
+- `RqlEventStore` persists the events in the rql `EventSchema` on disk.
+- `InMemoryEventStore` keeps the events in memory, mainly for tests.
+
+Next to the events, the store keeps the snapshots of aggregate state taken by `cqrs::snapshot`.
+
+Every stored event carries a hash of itself and of the event stored before it, so altering the event log by
+hand can be detected, see `database::hash_chain`.
+
+Appending uses optimistic concurrency control. Every append states which `aggregate_version` the writer
+believes the aggregate stream is at. If another writer has appended to the same stream in the meantime
+the append is rejected with an `AppendError::Conflict`, instead of both writers storing the same version
+and forking the history.
+
+# Example:
+
+```
+    let store = RqlEventStore::open("test_database_example")?;
+    let event = create_new_account_holder(full_name, ssn, date_of_birth, phone_number, home_address)?;
+
+    // a brand new aggregate has no events, so the expected version is 0
+    let position = store.append(event, 0)?;
+```
+*/
+
+use std::fmt;
+use chrono::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::snapshot::Snapshot;
+use crate::database::hash_chain::BrokenLink;
+
+#[allow(dead_code)]
+pub trait EventStore {
+    /// Appends the event if the aggregate stream is still at `expected_version`.
+    /// Use 0 as expected version for the first event of a new aggregate.
+    ///
+    /// Returns the global position assigned to the event, one higher than the last stored position.
+    fn append(&self, event: Event, expected_version: u32) -> Result<u64, AppendError>;
+
+    /// Returns all events of a single aggregate, ordered by aggregate_version.
+    fn read_stream(&self, aggregate_id: &str, aggregate_type: &str) -> Vec<Event> {
+        self.read_stream_from(aggregate_id, aggregate_type, 1)
+    }
+
+    /// Returns the events of a single aggregate with an aggregate_version of `from_version` or higher,
+    /// ordered by aggregate_version.
+    fn read_stream_from(&self, aggregate_id: &str, aggregate_type: &str, from_version: u32) -> Vec<Event>;
+
+    /// Returns the tail of the aggregate stream, i.e. the event with the highest aggregate_version.
+    fn last_event(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Event>;
+
+    /// Returns all events with a global position of `from_position` or higher, ordered by position.
+    /// Projections pass their last processed position + 1 to resume where they left off.
+    fn read_all(&self, from_position: u64) -> Vec<Event>;
+
+    /// Same as `read_all`, but returns the events exactly as they were stored, i.e. without upcasting them.
+    /// The hash chain is over the stored events.
+    fn read_all_as_stored(&self, from_position: u64) -> Vec<Event>;
+
+    /// Same as `read_stream`, but returns the events exactly as they were stored, i.e. without upcasting them.
+    fn read_stream_as_stored(&self, aggregate_id: &str, aggregate_type: &str) -> Vec<Event>;
+
+    /// Returns all events with a timestamp from `from` up to, but not including, `to`, ordered by position.
+    fn read_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event>;
+
+    /// Returns the events of a single aggregate with a timestamp from `from` up to, but not including, `to`,
+    /// ordered by aggregate_version.
+    fn read_stream_between(&self, aggregate_id: &str, aggregate_type: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+        self.read_stream(aggregate_id, aggregate_type)
+            .into_iter()
+            .filter(|event| event.timestamp >= from && event.timestamp < to)
+            .collect()
+    }
+
+    /// Returns the position of the last stored event, or 0 if the store is empty.
+    fn last_position(&self) -> u64;
+
+    /// Returns the highest aggregate_version stored for the aggregate, or 0 if it has no events.
+    fn latest_version(&self, aggregate_id: &str, aggregate_type: &str) -> u32;
+
+    /// Stores a snapshot of the aggregate state, next to the events.
+    fn save_snapshot(&self, snapshot: Snapshot);
+
+    /// Returns the snapshot of the aggregate with the highest aggregate_version, if any.
+    fn latest_snapshot(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Snapshot>;
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppendError {
+    /// The stream has moved on since the writer read it.
+    Conflict {
+        aggregate_id: String,
+        expected_version: u32,
+        actual_version: u32,
+    },
+    /// The event does not follow directly after the expected version.
+    InvalidVersion {
+        aggregate_id: String,
+        expected_version: u32,
+        event_version: u32,
+    },
+    /// The stored event log is broken, e.g. it can not be read, so nothing is appended to it.
+    BrokenLog(BrokenLink),
+}
+
+impl fmt::Display for AppendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppendError::Conflict { aggregate_id, expected_version, actual_version } => write!(
+                f,
+                "concurrency conflict on aggregate {}: expected version {}, but stream is at version {}",
+                aggregate_id, expected_version, actual_version
+            ),
+            AppendError::InvalidVersion { aggregate_id, expected_version, event_version } => write!(
+                f,
+                "event for aggregate {} has version {}, but must be {} to follow expected version {}",
+                aggregate_id, event_version, expected_version + 1, expected_version
+            ),
+            AppendError::BrokenLog(broken_link) => write!(f, "can not append to the event log: {}", broken_link),
+        }
+    }
+}
+
+impl std::error::Error for AppendError {}
+
+/// Checks that the event can be appended to a stream that is currently at `actual_version`.
+/// Shared by the store implementations, which must call it while holding their write lock.
+#[allow(dead_code)]
+pub fn check_expected_version(event: &Event, expected_version: u32, actual_version: u32) -> Result<(), AppendError> {
+    if event.aggregate_version != expected_version + 1 {
+        return Err(AppendError::InvalidVersion {
+            aggregate_id: event.aggregate_id.clone(),
+            expected_version,
+            event_version: event.aggregate_version,
+        })
+    }
+
+    if actual_version != expected_version {
+        return Err(AppendError::Conflict {
+            aggregate_id: event.aggregate_id.clone(),
+            expected_version,
+            actual_version,
+        })
+    }
+
+    Ok(())
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use crate::database;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use super::*;
+
+        // every test runs against both implementations, they must behave the same
+        fn stores() -> Vec<Box<dyn EventStore>> {
+            vec![
+                Box::new(database::ruql::setup()),
+                Box::new(InMemoryEventStore::new()),
+            ]
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn appends_first_event_of_new_aggregate() {
+            for store in stores() {
+                let event = new_event();
+                let aggregate_id = event.aggregate_id.clone();
+
+                let result = store.append(event, 0);
+
+                assert!(result.is_ok());
+                assert_eq!(store.latest_version(&aggregate_id, "AggregateType"), 1);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn appends_update_on_expected_version() {
+            for store in stores() {
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+                let aggregate_id = first.aggregate_id.clone();
+
+                store.append(first, 0).unwrap();
+                let result = store.append(second, 1);
+
+                assert!(result.is_ok());
+                assert_eq!(store.latest_version(&aggregate_id, "AggregateType"), 2);
+                assert_eq!(store.read_stream(&aggregate_id, "AggregateType").len(), 2);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn rejects_concurrent_append_of_same_version() {
+            for store in stores() {
+                let first = new_event();
+                store.append(first.clone(), 0).unwrap();
+
+                // two writers both read version 1 and build version 2 on top of it
+                let writer_a = first.update(HashMap::from([("a".into(), "A".into())]), HashMap::new(), "update");
+                let writer_b = first.update(HashMap::from([("a".into(), "B".into())]), HashMap::new(), "update");
+
+                let result_a = store.append(writer_a, 1);
+                let result_b = store.append(writer_b, 1);
+
+                assert!(result_a.is_ok());
+                assert_eq!(result_b, Err(AppendError::Conflict {
+                    aggregate_id: first.aggregate_id.clone(),
+                    expected_version: 1,
+                    actual_version: 2,
+                }));
+                assert_eq!(store.latest_version(&first.aggregate_id, "AggregateType"), 2);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn rejects_event_not_following_expected_version() {
+            for store in stores() {
+                let event = new_event();
+
+                let result = store.append(event.clone(), 3);
+
+                assert_eq!(result, Err(AppendError::InvalidVersion {
+                    aggregate_id: event.aggregate_id,
+                    expected_version: 3,
+                    event_version: 1,
+                }));
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reads_stream_of_single_aggregate_only() {
+            for store in stores() {
+                let first = new_event();
+                let other = new_event();
+                store.append(first.clone(), 0).unwrap();
+                store.append(other, 0).unwrap();
+
+                let stream = store.read_stream(&first.aggregate_id, "AggregateType");
+
+                assert_eq!(stream.len(), 1);
+                assert_eq!(stream[0].aggregate_id, first.aggregate_id);
+                assert_eq!(store.read_stream(&first.aggregate_id, "OtherType").len(), 0);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reads_stream_ordered_by_aggregate_version() {
+            for store in stores() {
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+                let third = second.update(HashMap::new(), HashMap::new(), "update");
+                let fourth = third.update(HashMap::new(), HashMap::new(), "update");
+                for (expected_version, event) in [first.clone(), second, third, fourth].into_iter().enumerate() {
+                    store.append(event, expected_version as u32).unwrap();
+                    // interleave other aggregates, so the stream is spread out over the log
+                    store.append(new_event(), 0).unwrap();
+                }
+
+                let versions: Vec<u32> = store.read_stream(&first.aggregate_id, "AggregateType")
+                    .iter()
+                    .map(|event| event.aggregate_version)
+                    .collect();
+
+                assert_eq!(versions, vec![1, 2, 3, 4]);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn last_event_is_tail_of_stream() {
+            for store in stores() {
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+                let third = second.update(HashMap::new(), HashMap::new(), "last_update");
+                store.append(first.clone(), 0).unwrap();
+                store.append(second, 1).unwrap();
+                store.append(third, 2).unwrap();
+
+                let last_event = store.last_event(&first.aggregate_id, "AggregateType").unwrap();
+
+                assert_eq!(last_event.aggregate_version, 3);
+                assert_eq!(last_event.event_name, "last_update");
+                assert!(store.last_event("unknown aggregate id", "AggregateType").is_none());
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn assigns_gap_free_positions_on_append() {
+            for store in stores() {
+                let last_position = store.last_position();
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+
+                let first_position = store.append(first, 0).unwrap();
+                let other_position = store.append(new_event(), 0).unwrap();
+                let second_position = store.append(second, 1).unwrap();
+
+                assert_eq!(first_position, last_position + 1);
+                assert_eq!(other_position, last_position + 2);
+                assert_eq!(second_position, last_position + 3);
+                assert_eq!(store.last_position(), last_position + 3);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn rejected_append_does_not_use_a_position() {
+            for store in stores() {
+                let first = new_event();
+                store.append(first.clone(), 0).unwrap();
+                let last_position = store.last_position();
+
+                let result = store.append(first, 0);
+
+                assert!(result.is_err());
+                assert_eq!(store.last_position(), last_position);
+                assert_eq!(store.append(new_event(), 0), Ok(last_position + 1));
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reads_all_from_position_in_order() {
+            for store in stores() {
+                let last_position = store.last_position();
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+                store.append(first.clone(), 0).unwrap();
+                store.append(new_event(), 0).unwrap();
+                store.append(second, 1).unwrap();
+
+                let all_positions: Vec<u64> = store.read_all(1).iter().map(|event| event.position).collect();
+                let expected_positions: Vec<u64> = (1..=last_position + 3).collect();
+                let resumed = store.read_all(last_position + 2);
+
+                assert_eq!(all_positions, expected_positions);
+                assert_eq!(resumed.len(), 2);
+                assert_eq!(resumed[1].aggregate_id, first.aggregate_id);
+                assert_eq!(resumed[1].aggregate_version, 2);
+                assert_eq!(store.read_all(last_position + 4).len(), 0);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reads_stream_from_version() {
+            for store in stores() {
+                let first = new_event();
+                let second = first.update(HashMap::new(), HashMap::new(), "update");
+                let third = second.update(HashMap::new(), HashMap::new(), "update");
+                store.append(first.clone(), 0).unwrap();
+                store.append(second, 1).unwrap();
+                store.append(third, 2).unwrap();
+
+                let versions: Vec<u32> = store.read_stream_from(&first.aggregate_id, "AggregateType", 2)
+                    .iter()
+                    .map(|event| event.aggregate_version)
+                    .collect();
+
+                assert_eq!(versions, vec![2, 3]);
+                assert_eq!(store.read_stream_from(&first.aggregate_id, "AggregateType", 4).len(), 0);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn returns_latest_snapshot_of_aggregate() {
+            for store in stores() {
+                let event = new_event();
+                let snapshot = |aggregate_id: &str, aggregate_version| Snapshot {
+                    aggregate_id: aggregate_id.into(),
+                    aggregate_type: "AggregateType".into(),
+                    aggregate_version,
+                    state: format!("{{\"version\":{}}}", aggregate_version),
+                };
+                store.save_snapshot(snapshot(&event.aggregate_id, 200));
+                store.save_snapshot(snapshot(&event.aggregate_id, 100));
+                store.save_snapshot(snapshot("other aggregate id", 300));
+
+                assert_eq!(store.latest_snapshot(&event.aggregate_id, "AggregateType"), Some(snapshot(&event.aggregate_id, 200)));
+                assert_eq!(store.latest_snapshot(&event.aggregate_id, "OtherType"), None);
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn reads_events_within_time_range() {
+            for store in stores() {
+                let day = |day| Utc.with_ymd_and_hms(2022, 8, day, 12, 0, 0).unwrap();
+                let mut first = new_event();
+                first.timestamp = day(1);
+                let mut second = first.update(HashMap::new(), HashMap::new(), "update");
+                second.timestamp = day(2);
+                let mut third = second.update(HashMap::new(), HashMap::new(), "update");
+                third.timestamp = day(3);
+                let mut other = new_event();
+                other.timestamp = day(2);
+                store.append(first.clone(), 0).unwrap();
+                store.append(second.clone(), 1).unwrap();
+                store.append(other.clone(), 0).unwrap();
+                store.append(third, 2).unwrap();
+
+                let all_on_day_2: Vec<String> = store.read_between(day(2), day(3))
+                    .iter()
+                    .map(|event| event.aggregate_id.clone())
+                    .collect();
+                let stream_until_day_3 = store.read_stream_between(&first.aggregate_id, "AggregateType", day(1), day(3));
+
+                assert_eq!(all_on_day_2, vec![first.aggregate_id.clone(), other.aggregate_id]);
+                assert_eq!(stream_until_day_3.iter().map(|event| event.aggregate_version).collect::<Vec<u32>>(), vec![1, 2]);
+                assert!(store.read_between(day(4), day(5)).is_empty());
+            }
+        }
+
+        fn new_event() -> Event {
+            let metadata = HashMap::new();
+            let deltas = HashMap::from([("a".into(), "1".into())]);
+            let aggregate_type = String::from("AggregateType");
+
+            Event::new(metadata, deltas, aggregate_type)
+        }
+    }
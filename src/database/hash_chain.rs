@@ -0,0 +1,394 @@
+/**
+Hash chain over the event log, so that altering stored events by hand can be detected.
+
+When an event is appended, the store sets its `hash` to the SHA-256 of the hash of the event at the position
+before it, followed by the event itself as canonical JSON, i.e. with sorted keys and without the hash. Every
+event thereby vouches for all events stored before it. `verify` walks the log from position 1 and recomputes
+every hash, and reports the first link that does not hold.
+
+`verify` scans the event table itself, not the index of an event store, so events without a position, or at
+the same position as another event, are reported too, instead of being skipped or hidden behind each other.
+
+The chain covers the events as stored, before upcasting and with personal data still encrypted, so upcasters
+and forgetting personal data do not break it. Every event must have a hash: events stored before the chain
+existed are added to it once by `RqlEventStore::chain_unchained_events`, see `rusty-bank migrate`, and until
+then `verify` fails. Removing events from the end of the log can not be detected from the log itself, compare
+the last hash with one kept elsewhere for that.
+
+# Example:
+```
+    let schema = database::event_schema::get_schema();
+
+    match verify(&schema) {
+        Ok(report) => println!("{}", report),   // verified the hash chain of 12 events
+        Err(broken_link) => eprintln!("{}", broken_link),
+    }
+```
+*/
+
+use std::fmt;
+use sha2::{Digest, Sha256};
+use crate::cqrs::event::Event;
+use crate::database::event_schema::EventSchema;
+use crate::database::hex::to_hex;
+
+/// The first link in the log that does not hold.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BrokenLink {
+    /// The event, or the hash of the event before it, was changed after it was stored.
+    Altered {
+        position: u64,
+        stored_hash: String,
+        computed_hash: String,
+    },
+    /// The event has no hash, but the events before it do.
+    MissingHash {
+        position: u64,
+    },
+    /// There is no event at the position, or the event there has another position.
+    MissingEvent {
+        position: u64,
+    },
+    /// The events at the start of the log have no hash, i.e. they were stored before the hash chain existed, or
+    /// their hashes were removed.
+    Unchained {
+        events: usize,
+    },
+    /// The event has no position, so it is not in the chain. Opening the event store gives events stored before
+    /// events had a position one, e.g. `rusty-bank migrate`.
+    Unpositioned {
+        aggregate_id: String,
+        aggregate_version: u32,
+    },
+    /// More than one event is stored at the position.
+    DuplicatePosition {
+        position: u64,
+    },
+    /// The event table can not be read or parsed, e.g. because it is half written or was edited by hand.
+    Unreadable {
+        reason: String,
+    },
+}
+
+impl fmt::Display for BrokenLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BrokenLink::Altered { position, stored_hash, computed_hash } => write!(
+                f,
+                "event at position {} was altered: stored hash {}, computed hash {}",
+                position, stored_hash, computed_hash
+            ),
+            BrokenLink::MissingHash { position } => write!(f, "event at position {} has no hash", position),
+            BrokenLink::MissingEvent { position } => write!(f, "event at position {} is missing", position),
+            BrokenLink::Unchained { events } => write!(
+                f,
+                "the first {} events have no hash, if they were stored before the hash chain add them with `rusty-bank migrate`",
+                events
+            ),
+            BrokenLink::Unpositioned { aggregate_id, aggregate_version } => write!(
+                f,
+                "event {} of aggregate {} has no position",
+                aggregate_version, aggregate_id
+            ),
+            BrokenLink::DuplicatePosition { position } => write!(f, "more than one event is stored at position {}", position),
+            BrokenLink::Unreadable { reason } => write!(f, "the event log can not be read: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BrokenLink {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainReport {
+    /// Events whose hash was verified.
+    pub events: usize,
+}
+
+impl fmt::Display for ChainReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "verified the hash chain of {} events", self.events)
+    }
+}
+
+/// Computes the hash of the event, stored after the event with `previous_hash`.
+/// The previous hash of the first event is empty. Events are stored with timestamps in microseconds, so the stores
+/// truncate the timestamp before hashing, and the event hashed is the event read back.
+pub fn hash(previous_hash: &str, event: &Event) -> String {
+    let mut unhashed = event.clone();
+    unhashed.hash = String::new();
+    // serde_json sorts the keys of maps, so the deltas and metadata always come out in the same order
+    let content = serde_json::to_value(&unhashed)
+        .and_then(|content| serde_json::to_string(&content))
+        .expect("events serialize to JSON");
+
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(content.as_bytes());
+
+    to_hex(&hasher.finalize())
+}
+
+/// Verifies the hash chain over all events in the event table, as it is on disk.
+pub fn verify(schema: &EventSchema) -> Result<ChainReport, BrokenLink> {
+    schema.try_reload().map_err(|error| BrokenLink::Unreadable { reason: error.to_string() })?;
+
+    verify_events(stored_events(schema)?)
+}
+
+/// Verifies the hash chain over the events, which must be all events of the log as stored, ordered by position.
+pub fn verify_events(events: impl IntoIterator<Item = Event>) -> Result<ChainReport, BrokenLink> {
+    match verify_links(events)? {
+        (0, chained) => Ok(ChainReport { events: chained }),
+        (unchained, _) => Err(BrokenLink::Unchained { events: unchained }),
+    }
+}
+
+/// Verifies the hash chain over the events in the event table after the ones at the start of the log that have
+/// no hash, and returns how many of those there are.
+pub fn count_unchained(schema: &EventSchema) -> Result<usize, BrokenLink> {
+    verify_links(stored_events(schema)?).map(|(unchained, _)| unchained)
+}
+
+/// All events in the event table, ordered by position. Fails at events that the positions do not order.
+fn stored_events(schema: &EventSchema) -> Result<Vec<Event>, BrokenLink> {
+    let mut events: Vec<Event> = schema.event().rows().map(|row| row.data.clone()).collect();
+
+    if let Some(event) = events.iter().find(|event| event.position == 0) {
+        return Err(BrokenLink::Unpositioned {
+            aggregate_id: event.aggregate_id.clone(),
+            aggregate_version: event.aggregate_version,
+        })
+    }
+    events.sort_by_key(|event| event.position);
+    if let Some(pair) = events.windows(2).find(|pair| pair[0].position == pair[1].position) {
+        return Err(BrokenLink::DuplicatePosition { position: pair[0].position })
+    }
+
+    Ok(events)
+}
+
+/// Returns how many events at the start have no hash, and how many events after them were verified.
+/// The chain starts at the first event with a hash, with an empty previous hash.
+fn verify_links(events: impl IntoIterator<Item = Event>) -> Result<(usize, usize), BrokenLink> {
+    let (mut unchained, mut chained) = (0, 0);
+    let mut previous_hash = String::new();
+
+    for (expected_position, event) in (1..).zip(events) {
+        if event.position != expected_position {
+            return Err(BrokenLink::MissingEvent { position: expected_position })
+        }
+
+        if event.hash.is_empty() {
+            if chained > 0 {
+                return Err(BrokenLink::MissingHash { position: event.position })
+            }
+            unchained += 1;
+            continue
+        }
+
+        let computed_hash = hash(&previous_hash, &event);
+        if event.hash != computed_hash {
+            return Err(BrokenLink::Altered {
+                position: event.position,
+                stored_hash: event.hash,
+                computed_hash,
+            })
+        }
+
+        chained += 1;
+        previous_hash = event.hash;
+    }
+
+    Ok((unchained, chained))
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use chrono::prelude::*;
+        use rql::prelude::*;
+        use crate::database;
+        use crate::database::encrypted_event_store::EncryptedEventStore;
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::key_store::{InMemoryKeyStore, KeyStore};
+        use crate::database::rql_event_store::RqlEventStore;
+        use super::*;
+
+        #[test]
+        #[serial_test::serial]
+        fn chains_appended_events() {
+            let store = database::ruql::setup();
+            let in_memory_store = InMemoryEventStore::new();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            in_memory_store.append(first.clone(), 0).unwrap();
+            in_memory_store.append(first.update(HashMap::from([("amount".into(), "10.00 SEK".into())]), HashMap::new(), "deposit"), 1).unwrap();
+
+            let events = in_memory_store.read_all_as_stored(1);
+
+            assert_eq!(verify(&store.schema), Ok(ChainReport { events: store.last_position() as usize }));
+            assert_eq!(verify_events(events.clone()), Ok(ChainReport { events: 2 }));
+            assert_eq!(events[1].hash, hash(&events[0].hash, &events[1]));
+            assert_eq!(events[0].hash.len(), 64);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn finds_event_altered_on_disk() {
+            let store = database::ruql::setup();
+            let altered_position = store.last_position() - 2;
+            // e.g. by editing event.yaml by hand
+            store.schema.event_mut()
+                .rows_mut()
+                .filter(|row| row.position == altered_position)
+                .for_each(|mut row| { row.metadata.insert("a".into(), "2".into()); });
+
+            let result = verify(&database::event_schema::get_schema());
+
+            assert!(matches!(result, Err(BrokenLink::Altered { position, .. }) if position == altered_position));
+        }
+
+        #[test]
+        fn chains_events_with_nanosecond_timestamps() {
+            let dir = "test_database_nanosecond_timestamps";
+            let _ = std::fs::remove_dir_all(dir);
+            let store = RqlEventStore::open(dir).unwrap();
+            let in_memory_store = InMemoryEventStore::new();
+            let mut event = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            event.timestamp = Utc.with_ymd_and_hms(2022, 8, 11, 8, 18, 53).unwrap() + chrono::Duration::nanoseconds(1_244_567);
+
+            store.append(event.clone(), 0).unwrap();
+            in_memory_store.append(event.clone(), 0).unwrap();
+
+            let stored = store.read_all_as_stored(1).remove(0);
+            assert_eq!(verify(&EventSchema::new(dir, HumanReadable).unwrap()), Ok(ChainReport { events: 1 }));
+            assert_eq!(verify_events(in_memory_store.read_all_as_stored(1)), Ok(ChainReport { events: 1 }));
+            assert_eq!(stored.timestamp.nanosecond(), 1_244_000);
+            assert_eq!(in_memory_store.read_all_as_stored(1), [stored]);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn finds_removed_and_unhashed_events() {
+            let events = three_chained_events();
+
+            let mut removed = events.clone();
+            removed.remove(1);
+            let mut unhashed = events.clone();
+            unhashed[2].hash = String::new();
+            let mut reordered = events.clone();
+            reordered.swap(1, 2);
+            reordered[1].position = 2;
+            reordered[2].position = 3;
+
+            assert_eq!(verify_events(removed), Err(BrokenLink::MissingEvent { position: 2 }));
+            assert_eq!(verify_events(unhashed), Err(BrokenLink::MissingHash { position: 3 }));
+            assert!(matches!(verify_events(reordered), Err(BrokenLink::Altered { position: 2, .. })));
+        }
+
+        #[test]
+        fn fails_for_events_without_hash() {
+            let mut stripped = three_chained_events();
+            stripped.iter_mut().for_each(|event| event.hash = String::new());
+            let mut stripped_start = three_chained_events();
+            stripped_start[0].hash = String::new();
+            stripped_start[1].hash = String::new();
+            stripped_start[2].hash = hash("", &stripped_start[2]);
+
+            assert_eq!(verify_events(stripped), Err(BrokenLink::Unchained { events: 3 }));
+            assert_eq!(verify_events(stripped_start), Err(BrokenLink::Unchained { events: 2 }));
+            assert_eq!(verify_events(Vec::new()), Ok(ChainReport { events: 0 }));
+        }
+
+        #[test]
+        fn finds_events_the_positions_do_not_order() {
+            let dir = "test_database_unordered_events";
+            let _ = std::fs::remove_dir_all(dir);
+            let schema = EventSchema::new(dir, HumanReadable).unwrap();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            // e.g. written by hand, or by a store that did not lock the log
+            schema.event_mut().insert(Event { position: 1, ..first.clone() });
+            schema.event_mut().insert(Event { position: 1, ..first.update(HashMap::new(), HashMap::new(), "deposit") });
+
+            assert_eq!(verify(&schema), Err(BrokenLink::DuplicatePosition { position: 1 }));
+
+            schema.event_mut().insert(Event { position: 0, aggregate_version: 3, ..first.clone() });
+
+            assert_eq!(verify(&schema), Err(BrokenLink::Unpositioned { aggregate_id: first.aggregate_id, aggregate_version: 3 }));
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn chains_events_stored_before_the_chain() {
+            unchain_first_events(&database::ruql::setup(), 3);
+            let store = RqlEventStore::open("test_database_example").unwrap();
+            assert_eq!(verify(&store.schema), Err(BrokenLink::Unchained { events: 3 }));
+
+            let chained = store.chain_unchained_events();
+
+            assert_eq!(chained, Ok(3));
+            assert_eq!(verify(&database::event_schema::get_schema()), Ok(ChainReport { events: store.last_position() as usize }));
+            assert_eq!(store.chain_unchained_events(), Ok(0));
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn does_not_chain_events_over_a_broken_link() {
+            let store = database::ruql::setup();
+            unchain_first_events(&store, 3);
+            store.schema.event_mut()
+                .rows_mut()
+                .filter(|row| row.position == 5)
+                .for_each(|mut row| { row.deltas.insert("a".into(), "2".into()); });
+            let store = RqlEventStore::open("test_database_example").unwrap();
+
+            assert!(matches!(store.chain_unchained_events(), Err(BrokenLink::Altered { position: 5, .. })));
+            assert!(store.read_all_as_stored(1)[..3].iter().all(|event| event.hash.is_empty()));
+        }
+
+        #[test]
+        fn forgetting_personal_data_keeps_chain_intact() {
+            let (events, keys) = (InMemoryEventStore::new(), InMemoryKeyStore::new());
+            let store = EncryptedEventStore::new(&events, &keys);
+            let new_event = crate::cqrs::account_holder::create_new_account_holder(
+                "Isak Törnros",
+                "19930625-7255",
+                "1993-06-25",
+                "0763-154177",
+                "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            store.append(new_event.clone(), 0).unwrap();
+
+            keys.delete_key(&new_event.aggregate_id);
+
+            assert_eq!(verify_events(store.read_all_as_stored(1)), Ok(ChainReport { events: 1 }));
+        }
+
+        /// Makes the first events look like they were stored before the hash chain, and the chain start after them.
+        fn unchain_first_events(store: &RqlEventStore, events: u64) {
+            let mut event_table = store.schema.event_mut();
+            let mut rows: Vec<_> = event_table.rows_mut().collect();
+            rows.sort_by_key(|row| row.position);
+
+            let mut previous_hash = String::new();
+            for mut row in rows {
+                row.hash = String::new();
+                if row.position > events {
+                    row.hash = hash(&previous_hash, &row);
+                    previous_hash = row.hash.clone();
+                }
+            }
+        }
+
+        fn three_chained_events() -> Vec<Event> {
+            let store = InMemoryEventStore::new();
+            for _ in 0..3 {
+                store.append(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 0).unwrap();
+            }
+
+            store.read_all_as_stored(1)
+        }
+    }
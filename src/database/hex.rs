@@ -0,0 +1,38 @@
+/**
+Hex encoding of binary values stored as text, like encryption keys and hashes.
+*/
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Returns None unless `hex` is exactly the hex encoding of `N` bytes.
+pub fn from_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    from_hex_vec(hex)?.try_into().ok()
+}
+
+pub fn from_hex_vec(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn round_trips() {
+            assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+            assert_eq!(from_hex::<3>("000fff"), Some([0, 15, 255]));
+            assert_eq!(from_hex::<3>("000ff"), None);
+            assert_eq!(from_hex::<2>("000fff"), None);
+            assert_eq!(from_hex_vec("0g"), None);
+        }
+    }
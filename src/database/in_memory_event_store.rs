@@ -0,0 +1,139 @@
+/**
+`EventStore` that keeps all events in memory, in the order they were appended.
+The event at index `i` has position `i + 1`. Like in the other stores, events are upcast when read.
+Streams are looked up through a `StreamIndex`, maintained on append.
+
+Nothing is written to disk, so tests using it do not share any state and can run in parallel.
+
+# Example:
+
+```
+    let store = InMemoryEventStore::new();
+    store.append(event, 0)?;
+```
+*/
+
+use std::sync::{RwLock, RwLockReadGuard};
+use chrono::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::snapshot::Snapshot;
+use crate::cqrs::upcaster::upcast;
+use crate::database::event_store::*;
+use crate::database::hash_chain;
+use crate::database::stream_index::StreamIndex;
+
+#[derive(Default)]
+pub struct InMemoryEventStore {
+    events: RwLock<Vec<Event>>,
+    index: RwLock<StreamIndex>,
+    snapshots: RwLock<Vec<Snapshot>>,
+}
+
+impl InMemoryEventStore {
+    #[allow(dead_code)]
+    pub fn new() -> InMemoryEventStore {
+        InMemoryEventStore::default()
+    }
+
+    fn read_index(&self) -> RwLockReadGuard<'_, StreamIndex> {
+        self.index.read().expect("Thread using in-memory event store panicked")
+    }
+
+    /// Looks up the events at the given positions, and upcasts them.
+    fn events_at(&self, positions: impl IntoIterator<Item = u64>) -> Vec<Event> {
+        self.stored_events_at(positions).into_iter().map(upcast).collect()
+    }
+
+    /// Looks up the events at the given positions, as they were stored.
+    fn stored_events_at(&self, positions: impl IntoIterator<Item = u64>) -> Vec<Event> {
+        let events = self.events.read().expect("Thread using in-memory event store panicked");
+
+        positions
+            .into_iter()
+            .filter_map(|position| events.get(position as usize - 1))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Like in `RqlEventStore`, the index is always locked before the events.
+impl EventStore for InMemoryEventStore {
+    fn append(&self, mut event: Event, expected_version: u32) -> Result<u64, AppendError> {
+        let mut index = self.index.write().expect("Thread using in-memory event store panicked");
+
+        let actual_version = index.latest_version(&event.aggregate_id, &event.aggregate_type);
+        check_expected_version(&event, expected_version, actual_version)?;
+
+        let mut events = self.events.write().expect("Thread using in-memory event store panicked");
+        let position = events.len() as u64 + 1;
+        event.position = position;
+        event.timestamp = event.timestamp.trunc_subsecs(6);
+        event.hash = hash_chain::hash(events.last().map_or("", |previous| previous.hash.as_str()), &event);
+        index.add(&event);
+        events.push(event);
+
+        Ok(position)
+    }
+
+    fn read_stream_from(&self, aggregate_id: &str, aggregate_type: &str, from_version: u32) -> Vec<Event> {
+        let index = self.read_index();
+
+        self.events_at(index.positions(aggregate_id, aggregate_type, from_version))
+    }
+
+    fn last_event(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Event> {
+        let index = self.read_index();
+
+        self.events_at(index.last_position_of(aggregate_id, aggregate_type)).pop()
+    }
+
+    fn read_all(&self, from_position: u64) -> Vec<Event> {
+        self.events.read().expect("Thread using in-memory event store panicked")
+            .iter()
+            .skip(from_position.saturating_sub(1) as usize)
+            .map(|event| upcast(event.clone()))
+            .collect()
+    }
+
+    fn read_all_as_stored(&self, from_position: u64) -> Vec<Event> {
+        self.events.read().expect("Thread using in-memory event store panicked")
+            .iter()
+            .skip(from_position.saturating_sub(1) as usize)
+            .cloned()
+            .collect()
+    }
+
+    fn read_stream_as_stored(&self, aggregate_id: &str, aggregate_type: &str) -> Vec<Event> {
+        let index = self.read_index();
+
+        self.stored_events_at(index.positions(aggregate_id, aggregate_type, 1))
+    }
+
+    fn read_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+        self.events.read().expect("Thread using in-memory event store panicked")
+            .iter()
+            .filter(|event| event.timestamp >= from && event.timestamp < to)
+            .map(|event| upcast(event.clone()))
+            .collect()
+    }
+
+    fn last_position(&self) -> u64 {
+        self.events.read().expect("Thread using in-memory event store panicked").len() as u64
+    }
+
+    fn latest_version(&self, aggregate_id: &str, aggregate_type: &str) -> u32 {
+        self.read_index().latest_version(aggregate_id, aggregate_type)
+    }
+
+    fn save_snapshot(&self, snapshot: Snapshot) {
+        self.snapshots.write().expect("Thread using in-memory event store panicked").push(snapshot);
+    }
+
+    fn latest_snapshot(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Snapshot> {
+        self.snapshots.read().expect("Thread using in-memory event store panicked")
+            .iter()
+            .filter(|snapshot| snapshot.aggregate_id == aggregate_id && snapshot.aggregate_type == aggregate_type)
+            .max_by_key(|snapshot| snapshot.aggregate_version)
+            .cloned()
+    }
+}
@@ -0,0 +1,21 @@
+/**
+Storage of the encryption keys of personal data, see `database::key_store`.
+
+The keys are kept in their own database directory, apart from the event log, so that deleting a key really
+removes it, while the events encrypted with it stay where they are.
+*/
+
+use rql::prelude::*;
+use rql::mashup;
+use crate::database::key_store::AggregateKey;
+
+schema! {
+  pub KeySchema {
+    key: AggregateKey,
+  }
+}
+
+#[allow(dead_code)]
+pub fn get_key_schema() -> KeySchema {
+    KeySchema::new("test_database_example_keys", HumanReadable).unwrap()
+}
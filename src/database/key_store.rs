@@ -0,0 +1,170 @@
+/**
+Key store for the per-aggregate keys that encrypt personal data in events, see `database::encrypted_event_store`.
+
+Every aggregate with personal data gets its own key when its first event with personal data is stored.
+Deleting the key is what forgets the personal data: the events stay in the event log, but the encrypted
+fields in them can never be read again.
+
+- `RqlKeyStore` persists the keys in the rql `KeySchema`, in a database directory of its own.
+- `InMemoryKeyStore` keeps the keys in memory, for tests.
+
+# Example:
+
+```
+    let keys = RqlKeyStore::open_default();
+    let key = keys.get_or_insert_key(&aggregate_id, new_key);
+
+    // forget the personal data of the aggregate
+    keys.delete_key(&aggregate_id);
+    assert_eq!(keys.key(&aggregate_id), None);
+```
+*/
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use rql::prelude::*;
+use crate::database;
+use crate::database::hex::{from_hex, to_hex};
+use crate::database::key_schema::KeySchema;
+
+/// A 256 bit AES key.
+pub type EncryptionKey = [u8; 32];
+
+#[allow(dead_code)]
+pub trait KeyStore {
+    /// Returns the key of the aggregate, if it has one.
+    fn key(&self, aggregate_id: &str) -> Option<EncryptionKey>;
+
+    /// Stores `key` as the key of the aggregate, unless it already has one. Returns the stored key, so two
+    /// writers that race to create the key of the same aggregate end up using the same one.
+    fn get_or_insert_key(&self, aggregate_id: &str, key: EncryptionKey) -> EncryptionKey;
+
+    /// Deletes the key of the aggregate, if it has one.
+    fn delete_key(&self, aggregate_id: &str);
+}
+
+/// The key of one aggregate, as stored in the `key` table.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AggregateKey {
+    pub aggregate_id: String,
+    /// Hex encoded.
+    pub key: String,
+}
+
+#[derive(Default)]
+pub struct InMemoryKeyStore {
+    keys: RwLock<HashMap<String, EncryptionKey>>,
+}
+
+impl InMemoryKeyStore {
+    #[allow(dead_code)]
+    pub fn new() -> InMemoryKeyStore {
+        InMemoryKeyStore::default()
+    }
+}
+
+impl KeyStore for InMemoryKeyStore {
+    fn key(&self, aggregate_id: &str) -> Option<EncryptionKey> {
+        self.keys.read().expect("Thread using in-memory key store panicked").get(aggregate_id).copied()
+    }
+
+    fn get_or_insert_key(&self, aggregate_id: &str, key: EncryptionKey) -> EncryptionKey {
+        *self.keys.write().expect("Thread using in-memory key store panicked").entry(aggregate_id.into()).or_insert(key)
+    }
+
+    fn delete_key(&self, aggregate_id: &str) {
+        self.keys.write().expect("Thread using in-memory key store panicked").remove(aggregate_id);
+    }
+}
+
+pub struct RqlKeyStore {
+    pub schema: KeySchema,
+}
+
+impl RqlKeyStore {
+    #[allow(dead_code)]
+    pub fn new(schema: KeySchema) -> RqlKeyStore {
+        RqlKeyStore { schema }
+    }
+
+    /// Opens, or creates, the key store in the given database directory.
+    #[allow(dead_code)]
+    pub fn open<P: AsRef<Path>>(dir: P) -> RqlKeyStore {
+        RqlKeyStore::new(KeySchema::new(dir, HumanReadable).unwrap())
+    }
+
+    /// Opens the key store in the default key directory.
+    #[allow(dead_code)]
+    pub fn open_default() -> RqlKeyStore {
+        RqlKeyStore::new(database::key_schema::get_key_schema())
+    }
+}
+
+impl KeyStore for RqlKeyStore {
+    fn key(&self, aggregate_id: &str) -> Option<EncryptionKey> {
+        self.schema.key()
+            .wher(|row| row.aggregate_id == aggregate_id)
+            .find_map(|row| from_hex(&row.key))
+    }
+
+    /// The key table stays locked from the lookup until the key is inserted.
+    fn get_or_insert_key(&self, aggregate_id: &str, key: EncryptionKey) -> EncryptionKey {
+        let mut key_table = self.schema.key_mut();
+        let existing = key_table
+            .wher(|row| row.aggregate_id == aggregate_id)
+            .find_map(|row| from_hex(&row.key));
+
+        match existing {
+            Some(existing) => existing,
+            None => {
+                key_table.insert(AggregateKey { aggregate_id: aggregate_id.into(), key: to_hex(&key) });
+                key
+            },
+        }
+    }
+
+    fn delete_key(&self, aggregate_id: &str) {
+        self.schema.key_mut().delete_where(|row| row.aggregate_id == aggregate_id);
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        #[serial_test::serial]
+        fn keeps_first_key_of_aggregate() {
+            for keys in key_stores() {
+                assert_eq!(keys.key("holder-1"), None);
+
+                assert_eq!(keys.get_or_insert_key("holder-1", [1; 32]), [1; 32]);
+                assert_eq!(keys.get_or_insert_key("holder-1", [2; 32]), [1; 32]);
+                assert_eq!(keys.key("holder-1"), Some([1; 32]));
+            }
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn deletes_key_of_aggregate_only() {
+            for keys in key_stores() {
+                keys.get_or_insert_key("holder-1", [1; 32]);
+                keys.get_or_insert_key("holder-2", [2; 32]);
+
+                keys.delete_key("holder-1");
+                keys.delete_key("holder-1");
+
+                assert_eq!(keys.key("holder-1"), None);
+                assert_eq!(keys.key("holder-2"), Some([2; 32]));
+            }
+        }
+
+        fn key_stores() -> Vec<Box<dyn KeyStore>> {
+            let schema = database::key_schema::get_key_schema();
+            schema.key_mut().delete_where(|_| true);
+
+            vec![Box::new(InMemoryKeyStore::new()), Box::new(RqlKeyStore::new(schema))]
+        }
+    }
@@ -1,2 +1,12 @@
+pub mod encrypted_event_store;
 pub mod event_schema;
-pub mod ruql;
\ No newline at end of file
+pub mod event_store;
+pub mod hash_chain;
+pub mod hex;
+pub mod in_memory_event_store;
+pub mod key_schema;
+pub mod key_store;
+pub mod projection_schema;
+pub mod rql_event_store;
+pub mod ruql;
+pub mod stream_index;
\ No newline at end of file
@@ -0,0 +1,29 @@
+/**
+Storage of the read models, persisted in the same database directory as the event log.
+
+Every table is written by a projector, see `projections::projector`. The `checkpoint` table
+records the position of the last event each projector has processed.
+*/
+
+use rql::prelude::*;
+use rql::mashup;
+use crate::projections::account::Account;
+use crate::projections::account_holder::AccountHolder;
+use crate::projections::balance::Balance;
+use crate::projections::ledger::Posting;
+use crate::projections::projector::Checkpoint;
+
+schema! {
+  pub ProjectionSchema {
+    account_holder: AccountHolder,
+    account: Account,
+    balance: Balance,
+    ledger: Posting,
+    checkpoint: Checkpoint,
+  }
+}
+
+#[allow(dead_code)]
+pub fn get_projection_schema() -> ProjectionSchema {
+    ProjectionSchema::new("test_database_example", HumanReadable).unwrap()
+}
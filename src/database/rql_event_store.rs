@@ -0,0 +1,466 @@
+/**
+`EventStore` backed by the rql `EventSchema`, persisted as human readable YAML on disk.
+Events are stored in the shape they were appended in, and upcast to the current shape when read.
+Snapshots of aggregate state are stored in the `snapshot` table of the same schema.
+
+The table is not indexed by rql, so the store keeps a `StreamIndex` of the stored events in memory, built when
+the store is opened and updated on every append. Reading a stream only touches the rows of that stream.
+
+More than one store can write to the same directory, in one process or in several. Every write locks the
+`event.lock` file in the directory and reloads the tables first, so the version check and the position of an
+appended event are always against the events on disk, and no store overwrites what another one stored.
+Reads only see the events appended by other stores once this store has appended, or is opened again.
+If the tables on disk can not be read, nothing is written: rql saves the whole table on every write, so
+writing over an unreadable file would replace the event log.
+
+Events stored before events had a position are at position 0. When the store is opened they get the positions
+after the highest one, in the order of their timestamps, then of their aggregate streams, see
+`assign_missing_positions`.
+
+# Example:
+
+```
+    let store = RqlEventStore::open("test_database_example")?;
+    store.append(event, 0)?;
+    let events = store.read_stream(&aggregate_id, "AccountHolder");
+```
+*/
+
+use std::collections::HashMap;
+use chrono::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+use rql::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::snapshot::Snapshot;
+use crate::cqrs::upcaster::upcast;
+use crate::database;
+use crate::database::event_schema::EventSchema;
+use crate::database::event_store::*;
+use crate::database::hash_chain::{self, BrokenLink};
+use crate::database::stream_index::StreamIndex;
+
+pub struct RqlEventStore {
+    /// Write events through the store only, the index does not see events written to the tables directly.
+    pub schema: EventSchema,
+    index: RwLock<RqlIndex>,
+    /// Locked by every write, by every store on the directory.
+    lock_path: PathBuf,
+}
+
+/// Finds events without scanning the event table.
+#[derive(Default)]
+struct RqlIndex {
+    streams: StreamIndex,
+    /// The rql id of the row holding the event at each position.
+    row_ids: HashMap<u64, Id<Event>>,
+}
+
+impl RqlIndex {
+    /// Fails if an event has no position, or the same position as another event. The index could only point
+    /// to one of them, and reads would return the wrong events, so the log has to be repaired first.
+    fn build(schema: &EventSchema) -> Result<RqlIndex, BrokenLink> {
+        let event_table = schema.event();
+
+        let mut row_ids = HashMap::new();
+        for row in event_table.rows() {
+            if row.position == 0 {
+                return Err(BrokenLink::Unpositioned {
+                    aggregate_id: row.aggregate_id.clone(),
+                    aggregate_version: row.aggregate_version,
+                })
+            }
+            if row_ids.insert(row.position, row.id).is_some() {
+                return Err(BrokenLink::DuplicatePosition { position: row.position })
+            }
+        }
+
+        Ok(RqlIndex {
+            streams: StreamIndex::build(event_table.rows().map(|row| row.data)),
+            row_ids,
+        })
+    }
+}
+
+impl RqlEventStore {
+    /// Builds the index from the events already in the schema. `dir` is the database directory of the schema.
+    /// Fails if the event log is broken in a way the index can not represent, see `RqlIndex::build`.
+    #[allow(dead_code)]
+    pub fn new<P: AsRef<Path>>(schema: EventSchema, dir: P) -> Result<RqlEventStore, BrokenLink> {
+        let store = RqlEventStore { schema, index: RwLock::default(), lock_path: dir.as_ref().join("event.lock") };
+        {
+            let _lock = store.lock_for_write()?;
+            assign_missing_positions(&store.schema);
+        }
+        *store.index.write().expect("Thread using event store index panicked") = RqlIndex::build(&store.schema)?;
+
+        Ok(store)
+    }
+
+    /// Opens, or creates, the event store in the given database directory.
+    #[allow(dead_code)]
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<RqlEventStore, BrokenLink> {
+        let schema = EventSchema::new(&dir, HumanReadable).map_err(|error| BrokenLink::Unreadable { reason: error.to_string() })?;
+
+        RqlEventStore::new(schema, dir)
+    }
+
+    /// Opens the event store in the default database directory.
+    #[allow(dead_code)]
+    pub fn open_default() -> Result<RqlEventStore, BrokenLink> {
+        RqlEventStore::open(database::event_schema::DEFAULT_DIR)
+    }
+
+    /// Waits until no other store writes to the directory, and keeps them from writing until the file is dropped.
+    /// Then reloads the tables, so writes are on top of what the other stores wrote. Fails if the tables can
+    /// not be read, the store must not write then.
+    fn lock_for_write(&self) -> Result<File, BrokenLink> {
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.lock_path)
+            .expect("can not open event store lock file");
+        lock.lock().expect("can not lock event store");
+        self.schema.try_reload().map_err(|error| BrokenLink::Unreadable { reason: error.to_string() })?;
+
+        Ok(lock)
+    }
+
+    fn read_index(&self) -> RwLockReadGuard<'_, RqlIndex> {
+        self.index.read().expect("Thread using event store index panicked")
+    }
+
+    /// Rewrites the events stored before the hash chain, i.e. the ones without a hash, in place. Only for one-off
+    /// migrations of the event log: events are never changed otherwise, and events in the chain can not be changed
+    /// without breaking it. `rewrite` returns whether it changed the event, and must keep its position and stream.
+    /// Returns how many events were rewritten.
+    pub fn rewrite_unchained_events(&self, mut rewrite: impl FnMut(&mut Event) -> bool) -> Result<usize, BrokenLink> {
+        let mut index = self.index.write().expect("Thread using event store index panicked");
+        let _lock = self.lock_for_write()?;
+        // positions are not rewritten, so a log the index can not represent is never changed
+        RqlIndex::build(&self.schema)?;
+
+        let mut rewritten = 0;
+        {
+            let mut event_table = self.schema.event_mut();
+            let ids: Vec<Id<Event>> = event_table.rows().filter(|row| row.hash.is_empty()).map(|row| row.id).collect();
+            for id in ids {
+                if rewrite(event_table.get_mut(id).expect("rows do not disappear while the table is locked")) {
+                    rewritten += 1;
+                }
+            }
+        }
+        *index = RqlIndex::build(&self.schema)?;
+
+        Ok(rewritten)
+    }
+
+    /// One-off migration that adds the events stored before the hash chain, i.e. the ones at the start of the log
+    /// without a hash, to the chain. The chain after them is verified first, then every hash is computed again
+    /// from the first event on. Returns how many events were added, or the link that keeps the log from being
+    /// chained.
+    pub fn chain_unchained_events(&self) -> Result<usize, BrokenLink> {
+        let mut index = self.index.write().expect("Thread using event store index panicked");
+        let _lock = self.lock_for_write()?;
+
+        let unchained = hash_chain::count_unchained(&self.schema)?;
+        if unchained == 0 {
+            return Ok(0)
+        }
+
+        {
+            let mut event_table = self.schema.event_mut();
+            let mut ids: Vec<(u64, Id<Event>)> = event_table.rows().map(|row| (row.position, row.id)).collect();
+            ids.sort();
+
+            let mut previous_hash = String::new();
+            for (_, id) in ids {
+                let event = event_table.get_mut(id).expect("rows do not disappear while the table is locked");
+                event.hash = hash_chain::hash(&previous_hash, event);
+                previous_hash = event.hash.clone();
+            }
+        }
+        *index = RqlIndex::build(&self.schema)?;
+
+        Ok(unchained)
+    }
+
+    /// Deletes the snapshots `delete` returns true for, they are rebuilt from the events when needed.
+    /// Returns how many snapshots were deleted.
+    pub fn delete_snapshots(&self, delete: impl Fn(&Snapshot) -> bool) -> Result<usize, BrokenLink> {
+        let _lock = self.lock_for_write()?;
+
+        Ok(self.schema.snapshot_mut().delete_where(delete))
+    }
+
+    /// Looks up the events at the given positions, and upcasts them.
+    fn events_at(&self, index: &RqlIndex, positions: impl IntoIterator<Item = u64>) -> Vec<Event> {
+        self.stored_events_at(index, positions).into_iter().map(upcast).collect()
+    }
+
+    /// Looks up the events at the given positions, as they were stored.
+    fn stored_events_at(&self, index: &RqlIndex, positions: impl IntoIterator<Item = u64>) -> Vec<Event> {
+        let event_table = self.schema.event();
+
+        positions
+            .into_iter()
+            .filter_map(|position| index.row_ids.get(&position))
+            .filter_map(|id| event_table.get(*id))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Gives the events stored without a position the positions after the highest one. They were stored before any
+/// event with a position, so the order they were stored in is only known from their timestamps. Events with the
+/// same timestamp are ordered by aggregate_id and aggregate_version, so every store assigns the same positions.
+fn assign_missing_positions(schema: &EventSchema) {
+    if !schema.event().rows().any(|row| row.position == 0) {
+        return
+    }
+
+    let mut event_table = schema.event_mut();
+    let last_position = event_table.rows().map(|row| row.position).max().unwrap_or(0);
+    let mut unpositioned: Vec<(DateTime<Utc>, String, u32, Id<Event>)> = event_table
+        .rows()
+        .filter(|row| row.position == 0)
+        .map(|row| (row.timestamp, row.aggregate_id.clone(), row.aggregate_version, row.id))
+        .collect();
+    unpositioned.sort_by(|a, b| (a.0, &a.1, a.2).cmp(&(b.0, &b.1, b.2)));
+
+    for (position, (.., id)) in (last_position + 1..).zip(unpositioned) {
+        event_table.get_mut(id).expect("rows do not disappear while the table is locked").position = position;
+    }
+}
+
+/// The index is always locked before the event table, so readers and writers can not deadlock.
+impl EventStore for RqlEventStore {
+    /// The write lock on the index, and the lock file, are held from the version check until the event is
+    /// inserted, so two writers can never both append the same version or get the same position.
+    fn append(&self, mut event: Event, expected_version: u32) -> Result<u64, AppendError> {
+        let mut index = self.index.write().expect("Thread using event store index panicked");
+        let _lock = self.lock_for_write().map_err(AppendError::BrokenLog)?;
+        // another store on the directory has appended since
+        if self.schema.event().len() != index.row_ids.len() {
+            *index = RqlIndex::build(&self.schema).map_err(AppendError::BrokenLog)?;
+        }
+
+        let actual_version = index.streams.latest_version(&event.aggregate_id, &event.aggregate_type);
+        check_expected_version(&event, expected_version, actual_version)?;
+
+        let position = index.streams.last_position() + 1;
+        event.position = position;
+        event.timestamp = event.timestamp.trunc_subsecs(6);
+        let previous_hash = self.stored_events_at(&index, [position - 1]).pop().map(|previous| previous.hash).unwrap_or_default();
+        event.hash = hash_chain::hash(&previous_hash, &event);
+
+        let id = self.schema.event_mut().insert(event.clone());
+        index.streams.add(&event);
+        index.row_ids.insert(position, id);
+
+        Ok(position)
+    }
+
+    fn read_stream_from(&self, aggregate_id: &str, aggregate_type: &str, from_version: u32) -> Vec<Event> {
+        let index = self.read_index();
+        let positions = index.streams.positions(aggregate_id, aggregate_type, from_version);
+
+        self.events_at(&index, positions)
+    }
+
+    fn last_event(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Event> {
+        let index = self.read_index();
+        let position = index.streams.last_position_of(aggregate_id, aggregate_type)?;
+
+        self.events_at(&index, [position]).pop()
+    }
+
+    fn read_all(&self, from_position: u64) -> Vec<Event> {
+        let index = self.read_index();
+        let positions = from_position.max(1)..=index.streams.last_position();
+
+        self.events_at(&index, positions)
+    }
+
+    fn read_all_as_stored(&self, from_position: u64) -> Vec<Event> {
+        let index = self.read_index();
+        let positions = from_position.max(1)..=index.streams.last_position();
+
+        self.stored_events_at(&index, positions)
+    }
+
+    fn read_stream_as_stored(&self, aggregate_id: &str, aggregate_type: &str) -> Vec<Event> {
+        let index = self.read_index();
+        let positions = index.streams.positions(aggregate_id, aggregate_type, 1);
+
+        self.stored_events_at(&index, positions)
+    }
+
+    /// Timestamps are not indexed, so this scans the event table.
+    fn read_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+        let mut events: Vec<Event> = self.schema.event()
+            .wher(|row| row.timestamp >= from && row.timestamp < to)
+            .select(|row| upcast(row.data.clone()))
+            .collect();
+
+        events.sort_by_key(|event| event.position);
+
+        events
+    }
+
+    fn last_position(&self) -> u64 {
+        self.read_index().streams.last_position()
+    }
+
+    fn latest_version(&self, aggregate_id: &str, aggregate_type: &str) -> u32 {
+        self.read_index().streams.latest_version(aggregate_id, aggregate_type)
+    }
+
+    /// Snapshots are only an optimization, so none is saved if the tables can not be read.
+    fn save_snapshot(&self, snapshot: Snapshot) {
+        if let Ok(_lock) = self.lock_for_write() {
+            self.schema.snapshot_mut().insert(snapshot);
+        }
+    }
+
+    fn latest_snapshot(&self, aggregate_id: &str, aggregate_type: &str) -> Option<Snapshot> {
+        self.schema.snapshot()
+            .wher(|row| row.aggregate_id == aggregate_id && row.aggregate_type == aggregate_type)
+            .max_by_key(|row| row.aggregate_version)
+            .map(|row| row.data.clone())
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use super::*;
+
+        #[test]
+        #[serial_test::serial]
+        fn reopened_store_indexes_stored_events() {
+            let store = database::ruql::setup();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            let second = first.update(HashMap::new(), HashMap::new(), "deposit");
+            store.append(first.clone(), 0).unwrap();
+            store.append(second, 1).unwrap();
+
+            let reopened = RqlEventStore::open("test_database_example").unwrap();
+
+            assert_eq!(reopened.read_stream(&first.aggregate_id, "Account"), store.read_stream(&first.aggregate_id, "Account"));
+            assert_eq!(reopened.latest_version(&first.aggregate_id, "Account"), 2);
+            assert_eq!(reopened.last_position(), store.last_position());
+            assert_eq!(reopened.read_all(1).len() as u64, store.last_position());
+        }
+
+        #[test]
+        fn positions_events_stored_before_positions() {
+            let dir = "test_database_legacy_positions";
+            let _ = std::fs::remove_dir_all(dir);
+            std::fs::create_dir_all(dir).unwrap();
+            // as stored before events had a position, in no particular order
+            std::fs::write(format!("{}/event.yaml", dir), r#"---
+8586ca26-87e1-4e60-9ec2-a4aa37a27bf8:
+  aggregate_id: 55FD3905-843D-2C2A-6540-CD036AC3AB38
+  aggregate_version: 2
+  event_name: update
+  timestamp: "2022-08-11 14:18:33.190571 UTC"
+  metadata: {}
+  deltas: {}
+  aggregate_type: AccountHolder
+835f58dd-66ae-4d6d-92b9-ffae8e25be9f:
+  aggregate_id: D4921C2F-3C63-61DB-0B5C-45EFAEFEFC0A
+  aggregate_version: 1
+  event_name: new
+  timestamp: "2022-08-11 14:18:33.190571 UTC"
+  metadata: {}
+  deltas: {}
+  aggregate_type: Transaction
+f8ec6d04-5271-4483-b2e8-dbb9e48fd353:
+  aggregate_id: 55FD3905-843D-2C2A-6540-CD036AC3AB38
+  aggregate_version: 1
+  event_name: new
+  timestamp: "2022-08-11 14:18:33.190383 UTC"
+  metadata: {}
+  deltas: {}
+  aggregate_type: AccountHolder
+"#).unwrap();
+
+            let store = RqlEventStore::open(dir).unwrap();
+            let events = store.read_all(1);
+
+            let order: Vec<(&str, u32, u64)> = events
+                .iter()
+                .map(|event| (&event.aggregate_id[..4], event.aggregate_version, event.position))
+                .collect();
+            assert_eq!(order, [("55FD", 1, 1), ("55FD", 2, 2), ("D492", 1, 3)]);
+            assert_eq!(store.read_stream("55FD3905-843D-2C2A-6540-CD036AC3AB38", "AccountHolder").len(), 2);
+            assert_eq!(RqlEventStore::open(dir).unwrap().read_all(1), events);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn refuses_events_at_the_same_position() {
+            let dir = "test_database_duplicate_positions";
+            let _ = std::fs::remove_dir_all(dir);
+            let store = RqlEventStore::open(dir).unwrap();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            store.append(first.clone(), 0).unwrap();
+            let mut other = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            other.position = 1;
+            // e.g. written by hand, or by a store that did not lock the log
+            EventSchema::new(dir, HumanReadable).unwrap().event_mut().insert(other);
+
+            let result = store.append(first.update(HashMap::new(), HashMap::new(), "deposit"), 1);
+
+            assert_eq!(result, Err(AppendError::BrokenLog(BrokenLink::DuplicatePosition { position: 1 })));
+            assert_eq!(RqlEventStore::open(dir).err(), Some(BrokenLink::DuplicatePosition { position: 1 }));
+            assert_eq!(store.read_all(1).len(), 1);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        fn does_not_write_over_an_unreadable_log() {
+            let dir = "test_database_unreadable_log";
+            let _ = std::fs::remove_dir_all(dir);
+            let store = RqlEventStore::open(dir).unwrap();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            store.append(first.clone(), 0).unwrap();
+            // e.g. half written, or edited by hand
+            let path = format!("{}/event.yaml", dir);
+            let unreadable = std::fs::read_to_string(&path).unwrap().replace("aggregate_version: 1", "aggregate_version: [");
+            std::fs::write(&path, &unreadable).unwrap();
+
+            let result = store.append(first.update(HashMap::new(), HashMap::new(), "deposit"), 1);
+            store.save_snapshot(Snapshot { aggregate_id: first.aggregate_id.clone(), aggregate_type: "Account".into(), aggregate_version: 1, state: "{}".into() });
+
+            assert!(matches!(result, Err(AppendError::BrokenLog(BrokenLink::Unreadable { .. }))));
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), unreadable);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn stores_on_same_directory_do_not_fork_streams() {
+            let store_a = database::ruql::setup();
+            let store_b = RqlEventStore::open("test_database_example").unwrap();
+            let first = Event::new(HashMap::new(), HashMap::new(), "Account".into());
+            let writer_a = first.update(HashMap::from([("a".into(), "A".into())]), HashMap::new(), "update");
+            let writer_b = first.update(HashMap::from([("a".into(), "B".into())]), HashMap::new(), "update");
+            store_a.append(first.clone(), 0).unwrap();
+            store_a.append(writer_a, 1).unwrap();
+
+            // store_b has not seen the events of store_a
+            let result_b = store_b.append(writer_b, 1);
+            let position_b = store_b.append(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 0).unwrap();
+
+            assert!(matches!(result_b, Err(AppendError::Conflict { actual_version: 2, .. })));
+            assert_eq!(position_b, store_a.last_position() + 1);
+            let reopened = RqlEventStore::open("test_database_example").unwrap();
+            assert_eq!(reopened.read_all(1).len() as u64, position_b);
+            assert_eq!(reopened.latest_version(&first.aggregate_id, "Account"), 2);
+        }
+    }
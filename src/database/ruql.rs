@@ -1,13 +1,17 @@
 use crate::cqrs::event::*;
 use std::collections::HashMap;
 use crate::database;
-use crate::database::event_schema::EventSchema;
+use crate::database::event_store::EventStore;
+use crate::database::rql_event_store::RqlEventStore;
 
-pub fn setup() -> EventSchema{
-    let db: EventSchema = database::event_schema::get_schema();
+pub fn setup() -> RqlEventStore{
+    let schema = database::event_schema::get_schema();
 
-    // delete all events
-    db.event_mut().delete_where(|_| true);
+    // delete all events, and the snapshots built from them, before the store indexes them
+    schema.event_mut().delete_where(|_| true);
+    schema.snapshot_mut().delete_where(|_| true);
+
+    let db: RqlEventStore = RqlEventStore::new(schema, database::event_schema::DEFAULT_DIR).unwrap();
 
     let event1 = create_new_event("AccountHolder");
     let event2 = create_new_event("AccountHolder");
@@ -20,15 +24,10 @@ pub fn setup() -> EventSchema{
     
 
     // todo generate events from event.rs and insert
-    // insert events
-    db.event_mut().insert(event1);
-    db.event_mut().insert(event2);
-    db.event_mut().insert(event3);
-    db.event_mut().insert(event4);
-    db.event_mut().insert(event5);
-    db.event_mut().insert(event6);
-    db.event_mut().insert(event7);
-    db.event_mut().insert(event8);
+    // insert events, all of them are the first event of a new aggregate
+    for event in [event1, event2, event3, event4, event5, event6, event7, event8] {
+        db.append(event, 0).unwrap();
+    }
 
     db
 }
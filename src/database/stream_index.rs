@@ -0,0 +1,138 @@
+/**
+Index from aggregate stream to the positions of its events, so the event stores can load a single stream
+without scanning the whole event log.
+
+The stores build the index from their events when they are opened, and add every event to it on append,
+while holding their write lock. Reading a stream then costs a lookup of the stream plus one lookup per event
+in it, no matter how many other events the log holds.
+
+# Example:
+```
+    let mut index = StreamIndex::build(events.iter());
+    index.add(&appended_event);
+
+    let positions = index.positions(&aggregate_id, "Account", 1);
+```
+*/
+
+use std::collections::HashMap;
+use crate::cqrs::event::Event;
+
+#[derive(Debug, Default)]
+pub struct StreamIndex {
+    /// aggregate_type -> aggregate_id -> (aggregate_version, position) of every event, ordered by aggregate_version
+    streams: HashMap<String, HashMap<String, Vec<(u32, u64)>>>,
+    last_position: u64,
+}
+
+impl StreamIndex {
+    /// Builds the index from already stored events, in any order.
+    pub fn build<'a>(events: impl IntoIterator<Item = &'a Event>) -> StreamIndex {
+        let mut events: Vec<&Event> = events.into_iter().collect();
+        events.sort_by_key(|event| event.aggregate_version);
+
+        let mut index = StreamIndex::default();
+        for event in events {
+            index.add(event);
+        }
+
+        index
+    }
+
+    /// Adds a stored event. The events of a stream must be added in aggregate_version order.
+    pub fn add(&mut self, event: &Event) {
+        self.streams
+            .entry(event.aggregate_type.clone())
+            .or_default()
+            .entry(event.aggregate_id.clone())
+            .or_default()
+            .push((event.aggregate_version, event.position));
+
+        self.last_position = self.last_position.max(event.position);
+    }
+
+    /// Returns the positions of the events of the stream with an aggregate_version of `from_version` or higher,
+    /// ordered by aggregate_version.
+    pub fn positions(&self, aggregate_id: &str, aggregate_type: &str, from_version: u32) -> Vec<u64> {
+        let stream = self.stream(aggregate_id, aggregate_type);
+        let start = stream.partition_point(|(version, _)| *version < from_version);
+
+        stream[start..].iter().map(|(_, position)| *position).collect()
+    }
+
+    /// Returns the position of the event with the highest aggregate_version in the stream.
+    pub fn last_position_of(&self, aggregate_id: &str, aggregate_type: &str) -> Option<u64> {
+        self.stream(aggregate_id, aggregate_type).last().map(|(_, position)| *position)
+    }
+
+    /// Returns the highest aggregate_version of the stream, or 0 if it has no events.
+    pub fn latest_version(&self, aggregate_id: &str, aggregate_type: &str) -> u32 {
+        self.stream(aggregate_id, aggregate_type).last().map_or(0, |(version, _)| *version)
+    }
+
+    /// Returns the highest position of all indexed events, or 0 if there are none.
+    pub fn last_position(&self) -> u64 {
+        self.last_position
+    }
+
+    fn stream(&self, aggregate_id: &str, aggregate_type: &str) -> &[(u32, u64)] {
+        self.streams
+            .get(aggregate_type)
+            .and_then(|streams| streams.get(aggregate_id))
+            .map_or(&[], |stream| stream.as_slice())
+    }
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use super::*;
+
+        #[test]
+        fn builds_index_from_unordered_events() {
+            let first = stored_event(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 1);
+            let other = stored_event(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 2);
+            let second = stored_event(first.update(HashMap::new(), HashMap::new(), "deposit"), 3);
+            let third = stored_event(second.update(HashMap::new(), HashMap::new(), "deposit"), 4);
+
+            let index = StreamIndex::build([&third, &other, &first, &second]);
+
+            assert_eq!(index.positions(&first.aggregate_id, "Account", 1), vec![1, 3, 4]);
+            assert_eq!(index.positions(&first.aggregate_id, "Account", 3), vec![4]);
+            assert_eq!(index.positions(&other.aggregate_id, "Account", 1), vec![2]);
+            assert_eq!(index.latest_version(&first.aggregate_id, "Account"), 3);
+            assert_eq!(index.last_position_of(&first.aggregate_id, "Account"), Some(4));
+            assert_eq!(index.last_position(), 4);
+        }
+
+        #[test]
+        fn adds_appended_events() {
+            let mut index = StreamIndex::default();
+            let first = stored_event(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 1);
+            let second = stored_event(first.update(HashMap::new(), HashMap::new(), "deposit"), 2);
+
+            index.add(&first);
+            index.add(&second);
+
+            assert_eq!(index.positions(&first.aggregate_id, "Account", 1), vec![1, 2]);
+            assert_eq!(index.latest_version(&first.aggregate_id, "Account"), 2);
+            assert_eq!(index.last_position(), 2);
+        }
+
+        #[test]
+        fn unknown_stream_is_empty() {
+            let first = stored_event(Event::new(HashMap::new(), HashMap::new(), "Account".into()), 1);
+            let index = StreamIndex::build([&first]);
+
+            assert!(index.positions("unknown aggregate id", "Account", 1).is_empty());
+            assert!(index.positions(&first.aggregate_id, "AccountHolder", 1).is_empty());
+            assert_eq!(index.latest_version("unknown aggregate id", "Account"), 0);
+            assert_eq!(index.last_position_of("unknown aggregate id", "Account"), None);
+        }
+
+        fn stored_event(mut event: Event, position: u64) -> Event {
+            event.position = position;
+            event
+        }
+    }
@@ -4,9 +4,104 @@ mod projections;
 // use rql::prelude::*;
 // use rql::mashup;
 
+use std::env;
+use projections::projector::Projector;
+
+/// Usage:
+///   rusty-bank                        resumes interrupted transfers and catches up all projections with the event log
+///   rusty-bank replay <projection>    wipes the projection and rebuilds it from all events
+///   rusty-bank trial-balance          prints the debits and credits per ledger account, fails if they do not balance
+///   rusty-bank verify                 checks the hash chain over the event log, fails at the first broken link
+///   rusty-bank migrate                encrypts the personal data of events stored before it was encrypted, and adds
+///                                     events stored before the hash chain to it
 fn main() {
     println!("Hello, world! Foo");
-    let _schema = database::event_schema::get_schema();
+    let args: Vec<String> = env::args().collect();
+
+    // the event log as stored, opening the event store would assign missing positions, or refuse duplicate ones
+    if args.get(1).map(|arg| arg.as_str()) == Some("verify") {
+        match database::hash_chain::verify(&database::event_schema::get_schema()) {
+            Ok(report) => println!("{}", report),
+            Err(broken_link) => {
+                eprintln!("{}", broken_link);
+                std::process::exit(1);
+            }
+        }
+        return
+    }
+
+    let events = match database::rql_event_store::RqlEventStore::open_default() {
+        Ok(events) => events,
+        Err(broken_link) => {
+            eprintln!("can not open the event log: {}, see `rusty-bank verify`", broken_link);
+            std::process::exit(1);
+        }
+    };
+    let keys = database::key_store::RqlKeyStore::open_default();
+    let store = database::encrypted_event_store::EncryptedEventStore::new(&events, &keys);
+    let projection_schema = database::projection_schema::get_projection_schema();
+
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("replay") => {
+            let projection_name = args.get(2).map(|arg| arg.as_str()).unwrap_or("");
+
+            match projections::projector::replay_by_name(&store, &projection_schema, projection_name) {
+                Some(report) => println!("{}", report),
+                None => {
+                    let names: Vec<String> = projections::projector::all_projectors()
+                        .iter()
+                        .map(|projector| projector.name().to_string())
+                        .collect();
+                    eprintln!("unknown projection '{}', expected one of: {}", projection_name, names.join(", "));
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some("trial-balance") => {
+            let ledger = projections::ledger::LedgerProjector;
+            let events = projections::projector::catch_up(&store, &projection_schema, &ledger);
+            println!("projector {} handled {} events", ledger.name(), events);
+
+            match projections::ledger::trial_balance(&projection_schema) {
+                Ok(trial_balance) => print!("{}", trial_balance),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some("migrate") => {
+            // encrypt first, events in the hash chain can not be rewritten any more
+            match database::encrypted_event_store::encrypt_stored_personal_data(&events, &keys) {
+                Ok(report) => println!("{}", report),
+                Err(broken_link) => {
+                    eprintln!("can not encrypt personal data: {}", broken_link);
+                    std::process::exit(1);
+                }
+            }
+
+            match events.chain_unchained_events() {
+                Ok(chained) => println!("added {} events stored before the hash chain to it", chained),
+                Err(broken_link) => {
+                    eprintln!("can not add events to the hash chain: {}", broken_link);
+                    std::process::exit(1);
+                }
+            }
+        },
+        _ => {
+            // finish the transfers that were interrupted, so no money is left in transit
+            for result in cqrs::transaction::resume_pending_transfers(&store) {
+                match result {
+                    Ok(transfer) => println!("resumed transfer {}: {:?}", transfer.aggregate_id, transfer.status),
+                    Err(error) => eprintln!("could not resume transfer: {}", error),
+                }
+            }
+            // bring the read models up to date with the events appended since the last run
+            for (projector, events) in projections::projector::catch_up_all(&store, &projection_schema, &projections::projector::all_projectors()) {
+                println!("projector {} handled {} events", projector, events);
+            }
+        }
+    }
 
     // projections::account_holder::AccountHolder {
 
@@ -1,9 +1,244 @@
+/**
+Read model of an Account, built by folding the events of the aggregate stream in order.
+
+The `AccountProjector` keeps the persisted `account` table up to date.
+
+# Example:
+```
+    let events = store.read_stream(&aggregate_id, "Account");
+    let account = Account::from_events(&events);
+
+    // or read it from the persisted projection
+    let account = get_account(&get_projection_schema(), &aggregate_id);
+```
+*/
+
+use rql::prelude::*;
+use crate::cqrs::account::AccountEvent;
+use crate::cqrs::event::*;
+use crate::cqrs::money::Money;
+use crate::cqrs::snapshot::Snapshotable;
+use crate::database::projection_schema::ProjectionSchema;
+use crate::projections::balance::{add_to_balance, balance_change};
+use crate::projections::projector::Projector;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum AccountStatus {
+  #[default]
+  Open,
+  Frozen,
+  Closed,
+}
+
+/// Read model of an Account, owned by the AccountHolder with aggregate id `account_holder_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Account {
+  pub aggregate_id: String,
+  pub account_holder_id: String,
+  #[serde(default)]
+  pub status: AccountStatus,
+  /// Balance, in the currency the account was opened in.
+  #[serde(default)]
+  pub balance: Money,
+  /// How far below zero the balance may go.
+  #[serde(default)]
+  pub overdraft_limit: Money,
+  pub aggregate_version: u32,
+}
+
+impl Account {
+  /// Builds the read model from the events of one aggregate, ordered by aggregate_version.
+  /// Returns None if there are no events.
+  pub fn from_events(events: &[Event]) -> Option<Account> {
+    let first = events.first()?;
+    let mut account = Account {
+      aggregate_id: first.aggregate_id.clone(),
+      ..Default::default()
+    };
+
+    for event in events {
+      account.apply(event);
+    }
+
+    Some(account)
+  }
+
+  /// Applies a single event on top of the current state.
+  pub fn apply(&mut self, event: &Event) {
+    // events this version of the read model does not know are skipped
+    if let Ok(domain_event) = event.decode::<AccountEvent>() {
+      match domain_event {
+        AccountEvent::New { account_holder_id, currency } => {
+          self.account_holder_id = account_holder_id;
+          self.status = AccountStatus::Open;
+          self.balance = Money::zero(currency);
+          self.overdraft_limit = Money::zero(currency);
+        },
+        AccountEvent::FreezeAccount => self.status = AccountStatus::Frozen,
+        AccountEvent::UnfreezeAccount => self.status = AccountStatus::Open,
+        AccountEvent::CloseAccount => self.status = AccountStatus::Closed,
+        AccountEvent::SetOverdraftLimit { overdraft_limit } => self.overdraft_limit = overdraft_limit,
+        AccountEvent::Deposit { .. }
+        | AccountEvent::Withdraw { .. }
+        | AccountEvent::ChargeFee { .. }
+        | AccountEvent::PayInterest { .. }
+        | AccountEvent::Debit { .. }
+        | AccountEvent::Credit { .. }
+        | AccountEvent::ReverseDebit { .. } => {
+          if let Some(change) = balance_change(&domain_event) {
+            self.balance = add_to_balance(self.balance, change);
+          }
+        },
+      }
+    }
+
+    self.aggregate_version = event.aggregate_version;
+  }
+}
+
+impl Snapshotable for Account {
+  const AGGREGATE_TYPE: &'static str = "Account";
+
+  fn from_events(events: &[Event]) -> Option<Account> {
+    Account::from_events(events)
+  }
+
+  fn apply(&mut self, event: &Event) {
+    Account::apply(self, event)
+  }
+
+  fn aggregate_version(&self) -> u32 {
+    self.aggregate_version
+  }
+}
+
+/// Keeps the `account` projection table up to date.
+pub struct AccountProjector;
+
+impl Projector for AccountProjector {
+  fn name(&self) -> &str {
+    "account"
+  }
+
+  fn handle(&self, schema: &ProjectionSchema, event: &Event) {
+    if event.aggregate_type != "Account" {
+      return
+    }
+
+    let mut account_table = schema.account_mut();
+    let existing = account_table
+      .wher(|row| row.aggregate_id == event.aggregate_id)
+      .select(|row| row.id)
+      .next();
+
+    match existing {
+      Some(id) => {
+        let account = account_table.get_mut(id).unwrap();
+        // skip events that are already applied, so handling an event twice is harmless
+        if event.aggregate_version > account.aggregate_version {
+          account.apply(event);
+        }
+      },
+      None => {
+        account_table.insert(Account::from_events(std::slice::from_ref(event)).unwrap());
+      }
+    }
+  }
+
+  fn reset(&self, schema: &ProjectionSchema) {
+    schema.account_mut().delete_where(|_| true);
+  }
+}
+
+/// Reads an Account from the persisted projection.
+#[allow(dead_code)]
+pub fn get_account(schema: &ProjectionSchema, aggregate_id: &str) -> Option<Account> {
+  schema.account()
+    .wher(|row| row.aggregate_id == aggregate_id)
+    .select(|row| row.data.clone())
+    .next()
+}
+
+/// Reads all Accounts owned by an AccountHolder from the persisted projection.
+#[allow(dead_code)]
+pub fn get_accounts_by_account_holder(schema: &ProjectionSchema, account_holder_id: &str) -> Vec<Account> {
+  schema.account()
+    .wher(|row| row.account_holder_id == account_holder_id)
+    .select(|row| row.data.clone())
+    .collect()
+}
+
 #[cfg(test)]
     mod tests {
+        use std::collections::HashMap;
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::projection_schema;
+        use crate::cqrs::money::Currency;
+        use crate::projections::projector;
         use super::*;
 
         #[test]
-        fn internal() {
-            assert_eq!(4, internal_adder(2, 2));
+        fn follows_account_lifecycle() {
+            let open_event = open_account_event("holder-1");
+            let freeze_event = open_event.update_with(&AccountEvent::FreezeAccount, HashMap::new());
+            let unfreeze_event = freeze_event.update_with(&AccountEvent::UnfreezeAccount, HashMap::new());
+            let close_event = unfreeze_event.update_with(&AccountEvent::CloseAccount, HashMap::new());
+
+            let opened = Account::from_events(std::slice::from_ref(&open_event)).unwrap();
+            let frozen = Account::from_events(&[open_event.clone(), freeze_event.clone()]).unwrap();
+            let unfrozen = Account::from_events(&[open_event.clone(), freeze_event.clone(), unfreeze_event.clone()]).unwrap();
+            let closed = Account::from_events(&[open_event, freeze_event, unfreeze_event, close_event]).unwrap();
+
+            assert_eq!(opened.status, AccountStatus::Open);
+            assert_eq!(opened.account_holder_id, "holder-1");
+            assert_eq!(frozen.status, AccountStatus::Frozen);
+            assert_eq!(unfrozen.status, AccountStatus::Open);
+            assert_eq!(closed.status, AccountStatus::Closed);
+            assert_eq!(closed.aggregate_version, 4);
+            assert_eq!(closed.account_holder_id, "holder-1");
+        }
+
+        #[test]
+        fn keeps_balance_and_overdraft_limit() {
+            let open_event = open_account_event("holder-1");
+            let deposit_event = open_event.update_with(&AccountEvent::Deposit { amount: "100.00 SEK".parse().unwrap() }, HashMap::new());
+            let withdraw_event = deposit_event.update_with(&AccountEvent::Withdraw { amount: "25.50 SEK".parse().unwrap() }, HashMap::new());
+            let overdraft_event = withdraw_event.update_with(&AccountEvent::SetOverdraftLimit { overdraft_limit: "500.00 SEK".parse().unwrap() }, HashMap::new());
+
+            let account = Account::from_events(&[open_event, deposit_event, withdraw_event, overdraft_event]).unwrap();
+
+            assert_eq!(account.balance, Money::new(7450, Currency::SEK));
+            assert_eq!(account.overdraft_limit, Money::new(50000, Currency::SEK));
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn projector_persists_accounts_per_owner() {
+            let schema = projection_schema::get_projection_schema();
+            schema.account_mut().delete_where(|_| true);
+            schema.checkpoint_mut().delete_where(|_| true);
+            let store = InMemoryEventStore::new();
+            let first = open_account_event("holder-1");
+            let second = open_account_event("holder-1");
+            let other = open_account_event("holder-2");
+            store.append(first.clone(), 0).unwrap();
+            store.append(second, 0).unwrap();
+            store.append(other, 0).unwrap();
+            store.append(first.update_with(&AccountEvent::FreezeAccount, HashMap::new()), 1).unwrap();
+
+            projector::catch_up(&store, &schema, &AccountProjector);
+
+            assert_eq!(get_account(&schema, &first.aggregate_id).unwrap().status, AccountStatus::Frozen);
+            assert_eq!(get_accounts_by_account_holder(&schema, "holder-1").len(), 2);
+            assert_eq!(get_accounts_by_account_holder(&schema, "holder-2").len(), 1);
+        }
+
+        fn open_account_event(account_holder_id: &str) -> Event {
+            let domain_event = AccountEvent::New {
+                account_holder_id: account_holder_id.into(),
+                currency: Currency::SEK,
+            };
+            Event::from_domain_event(&domain_event, HashMap::new())
         }
-    }
\ No newline at end of file
+    }
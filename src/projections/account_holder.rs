@@ -1,21 +1,368 @@
+/**
+Read model of an AccountHolder, built by folding the events of the aggregate stream in order.
+
+The `AccountHolderProjector` keeps the persisted `account_holder` table up to date.
+
+# Example:
+```
+    let events = store.read_stream(&aggregate_id, "AccountHolder");
+    let account_holder = AccountHolder::from_events(&events);
+
+    // or read it from the persisted projection
+    let account_holder = get_account_holder(&get_projection_schema(), &aggregate_id);
+```
+*/
+
+use chrono::prelude::*;
 use rql::prelude::*;
+use crate::cqrs::account_holder::{AccountHolderChanges, AccountHolderEvent};
+use crate::cqrs::address::Address;
+use crate::cqrs::event::*;
+use crate::cqrs::phone_number::PhoneNumber;
+use crate::cqrs::snapshot::Snapshotable;
+use crate::database::projection_schema::ProjectionSchema;
+use crate::projections::projector::Projector;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 pub struct AccountHolder {
   pub aggregate_id: String,
   pub full_name: String,
-  pub social_security_number: String, 
-  pub date_of_birth: String, 
-  pub phone_number: String, 
-  pub home_address: String,
+  pub social_security_number: String,
+  pub date_of_birth: String,
+  pub phone_number: PhoneNumber,
+  pub home_address: Address,
+  #[serde(default)]
+  pub aggregate_version: u32,
+  #[serde(default)]
+  pub deleted: bool,
+  /// When the AccountHolder was deleted, None unless it is deleted.
+  #[serde(default)]
+  pub deleted_at: Option<DateTime<Utc>>,
+  /// Whether the personal data of the AccountHolder is forgotten, the personal data fields are then empty.
+  #[serde(default)]
+  pub forgotten: bool,
+}
+
+impl AccountHolder {
+  /// Builds the read model from the events of one aggregate, ordered by aggregate_version.
+  /// Returns None if there are no events.
+  #[allow(dead_code)]
+  pub fn from_events(events: &[Event]) -> Option<AccountHolder> {
+    let first = events.first()?;
+    let mut account_holder = AccountHolder {
+      aggregate_id: first.aggregate_id.clone(),
+      ..Default::default()
+    };
+
+    for event in events {
+      account_holder.apply(event);
+    }
+
+    Some(account_holder)
+  }
+
+  /// Applies a single event on top of the current state.
+  /// Updates only carry the fields that changed, every other field keeps its value.
+  pub fn apply(&mut self, event: &Event) {
+    // events this version of the read model does not know are skipped
+    if let Ok(domain_event) = event.decode::<AccountHolderEvent>() {
+      match domain_event {
+        AccountHolderEvent::New { full_name, social_security_number, date_of_birth, phone_number, home_address } => {
+          self.full_name = full_name;
+          self.social_security_number = social_security_number;
+          self.date_of_birth = date_of_birth;
+          self.phone_number = phone_number;
+          self.home_address = home_address;
+        },
+        AccountHolderEvent::UpdateAccountHolderInfo(changes) => self.apply_changes(changes),
+        AccountHolderEvent::DeleteAccountHolder { .. } => {
+          self.deleted = true;
+          self.deleted_at = Some(event.timestamp);
+        },
+        AccountHolderEvent::RestoreAccountHolder => {
+          self.deleted = false;
+          self.deleted_at = None;
+        },
+        AccountHolderEvent::ForgetAccountHolder => {
+          self.full_name = String::new();
+          self.social_security_number = String::new();
+          self.date_of_birth = String::new();
+          self.phone_number = PhoneNumber::default();
+          self.home_address = Address::default();
+          self.forgotten = true;
+        },
+      }
+    }
+
+    self.aggregate_version = event.aggregate_version;
+  }
+
+  fn apply_changes(&mut self, changes: AccountHolderChanges) {
+    let AccountHolderChanges { full_name, social_security_number, date_of_birth, phone_number, home_address } = changes;
+
+    if let Some(full_name) = full_name {
+      self.full_name = full_name;
+    }
+    if let Some(social_security_number) = social_security_number {
+      self.social_security_number = social_security_number;
+    }
+    if let Some(date_of_birth) = date_of_birth {
+      self.date_of_birth = date_of_birth;
+    }
+    if let Some(phone_number) = phone_number {
+      self.phone_number = phone_number;
+    }
+    if let Some(home_address) = home_address {
+      self.home_address = home_address;
+    }
+  }
+}
+
+impl Snapshotable for AccountHolder {
+  const AGGREGATE_TYPE: &'static str = "AccountHolder";
+
+  fn from_events(events: &[Event]) -> Option<AccountHolder> {
+    AccountHolder::from_events(events)
+  }
+
+  fn apply(&mut self, event: &Event) {
+    AccountHolder::apply(self, event)
+  }
+
+  fn aggregate_version(&self) -> u32 {
+    self.aggregate_version
+  }
+}
+
+/// Keeps the `account_holder` projection table up to date.
+pub struct AccountHolderProjector;
+
+impl Projector for AccountHolderProjector {
+  fn name(&self) -> &str {
+    "account_holder"
+  }
+
+  fn handle(&self, schema: &ProjectionSchema, event: &Event) {
+    if event.aggregate_type != "AccountHolder" {
+      return
+    }
+
+    let mut account_holder_table = schema.account_holder_mut();
+    let existing = account_holder_table
+      .wher(|row| row.aggregate_id == event.aggregate_id)
+      .select(|row| row.id)
+      .next();
+
+    match existing {
+      Some(id) => {
+        let account_holder = account_holder_table.get_mut(id).unwrap();
+        // skip events that are already applied, so handling an event twice is harmless
+        if event.aggregate_version > account_holder.aggregate_version {
+          account_holder.apply(event);
+        }
+      },
+      None => {
+        account_holder_table.insert(AccountHolder::from_events(std::slice::from_ref(event)).unwrap());
+      }
+    }
+  }
+
+  fn reset(&self, schema: &ProjectionSchema) {
+    schema.account_holder_mut().delete_where(|_| true);
+  }
+}
+
+/// Reads an AccountHolder from the persisted projection.
+#[allow(dead_code)]
+pub fn get_account_holder(schema: &ProjectionSchema, aggregate_id: &str) -> Option<AccountHolder> {
+  schema.account_holder()
+    .wher(|row| row.aggregate_id == aggregate_id)
+    .select(|row| row.data.clone())
+    .next()
 }
 
 #[cfg(test)]
     mod tests {
-        // use super::*;
+        use std::collections::HashMap;
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::projection_schema;
+        use crate::projections::projector;
+        use super::*;
 
         #[test]
         fn internal() {
-            
+
+        }
+
+        #[test]
+        fn builds_account_holder_from_new_event() {
+            let event = new_account_holder_event();
+
+            let account_holder = AccountHolder::from_events(std::slice::from_ref(&event)).unwrap();
+
+            assert_eq!(account_holder, AccountHolder {
+              aggregate_id: event.aggregate_id,
+              full_name: "Isak Törnros".into(),
+              social_security_number: "19930625-7255".into(),
+              date_of_birth: "1993-06-25".into(),
+              phone_number: "+46763154177".parse().unwrap(),
+              home_address: Address::new("Nöbbelövs Torg 37", "226 52", "LUND", "SE").unwrap(),
+              aggregate_version: 1,
+              deleted: false,
+              deleted_at: None,
+              forgotten: false,
+            });
+        }
+
+        #[test]
+        fn applies_partial_updates_in_order() {
+            let new_event = new_account_holder_event();
+            let update_1 = new_event.update(
+              HashMap::from([("full_name".into(), "Emil Törnros".into())]),
+              HashMap::new(),
+              "update_account_holder_info",
+            );
+            let update_2 = update_1.update(
+              HashMap::from([
+                ("full_name".into(), "Olle Törnros".into()),
+                ("phone_number".into(), "+46701234567".into()),
+              ]),
+              HashMap::new(),
+              "update_account_holder_info",
+            );
+
+            let account_holder = AccountHolder::from_events(&[new_event, update_1, update_2]).unwrap();
+
+            assert_eq!(account_holder.full_name, "Olle Törnros");
+            assert_eq!(account_holder.phone_number.to_string(), "+46701234567");
+            assert_eq!(account_holder.home_address.to_string(), "Nöbbelövs Torg 37, 226 52 LUND, SE");
+            assert_eq!(account_holder.aggregate_version, 3);
+            assert!(!account_holder.deleted);
+        }
+
+        #[test]
+        fn marks_account_holder_as_deleted() {
+            let new_event = new_account_holder_event();
+            let delete_event = new_event.update(
+              HashMap::from([("reason".into(), "requested by the account holder".into())]),
+              HashMap::new(),
+              "delete_account_holder",
+            );
+
+            let account_holder = AccountHolder::from_events(&[new_event, delete_event.clone()]).unwrap();
+
+            assert!(account_holder.deleted);
+            assert_eq!(account_holder.deleted_at, Some(delete_event.timestamp));
+            assert_eq!(account_holder.aggregate_version, 2);
+            assert_eq!(account_holder.full_name, "Isak Törnros");
+        }
+
+        #[test]
+        fn clears_personal_data_of_forgotten_account_holder() {
+            let new_event = new_account_holder_event();
+            let delete_event = new_event.update(
+              HashMap::from([("reason".into(), "requested by the account holder".into())]),
+              HashMap::new(),
+              "delete_account_holder",
+            );
+            let forget_event = delete_event.update(HashMap::new(), HashMap::new(), "forget_account_holder");
+
+            let account_holder = AccountHolder::from_events(&[new_event.clone(), delete_event, forget_event]).unwrap();
+
+            assert_eq!(account_holder, AccountHolder {
+              aggregate_id: new_event.aggregate_id,
+              aggregate_version: 3,
+              deleted: account_holder.deleted,
+              deleted_at: account_holder.deleted_at,
+              forgotten: true,
+              ..Default::default()
+            });
+            assert!(account_holder.deleted);
+        }
+
+        #[test]
+        fn no_events_gives_no_account_holder() {
+            assert_eq!(AccountHolder::from_events(&[]), None);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn projector_persists_account_holder() {
+            let schema = setup_projection_schema();
+            let store = InMemoryEventStore::new();
+            let new_event = new_account_holder_event();
+            let update_event = new_event.update(
+              HashMap::from([("full_name".into(), "Emil Törnros".into())]),
+              HashMap::new(),
+              "update_account_holder_info",
+            );
+            store.append(new_event.clone(), 0).unwrap();
+            store.append(update_event, 1).unwrap();
+
+            projector::catch_up(&store, &schema, &AccountHolderProjector);
+
+            let account_holder = get_account_holder(&schema, &new_event.aggregate_id).unwrap();
+            assert_eq!(account_holder.full_name, "Emil Törnros");
+            assert_eq!(account_holder.aggregate_version, 2);
+            assert_eq!(schema.account_holder().rows().count(), 1);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn projector_ignores_events_it_has_already_applied() {
+            let schema = setup_projection_schema();
+            let new_event = new_account_holder_event();
+            let update_event = new_event.update(
+              HashMap::from([("full_name".into(), "Emil Törnros".into())]),
+              HashMap::new(),
+              "update_account_holder_info",
+            );
+
+            AccountHolderProjector.handle(&schema, &new_event);
+            AccountHolderProjector.handle(&schema, &update_event);
+            // e.g. after a crash between handling the event and saving the checkpoint
+            AccountHolderProjector.handle(&schema, &new_event);
+
+            let account_holder = get_account_holder(&schema, &new_event.aggregate_id).unwrap();
+            assert_eq!(account_holder.full_name, "Emil Törnros");
+            assert_eq!(account_holder.aggregate_version, 2);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn replay_rebuilds_account_holder_table() {
+            let schema = setup_projection_schema();
+            let store = InMemoryEventStore::new();
+            let new_event = new_account_holder_event();
+            store.append(new_event.clone(), 0).unwrap();
+            projector::catch_up(&store, &schema, &AccountHolderProjector);
+            // the projection has drifted from the event log, e.g. after changing how it is computed
+            schema.account_holder_mut().rows_mut().for_each(|mut row| row.full_name = "wrong".into());
+
+            let report = projector::replay(&store, &schema, &AccountHolderProjector);
+
+            let account_holder = get_account_holder(&schema, &new_event.aggregate_id).unwrap();
+            assert_eq!(report.events, 1);
+            assert_eq!(account_holder.full_name, "Isak Törnros");
+            assert_eq!(schema.account_holder().rows().count(), 1);
+        }
+
+        fn setup_projection_schema() -> ProjectionSchema {
+            let schema = projection_schema::get_projection_schema();
+            schema.account_holder_mut().delete_where(|_| true);
+            schema.checkpoint_mut().delete_where(|_| true);
+            schema
+        }
+
+        fn new_account_holder_event() -> Event {
+            create_new_account_holder(
+              "Isak Törnros",
+              "19930625-7255",
+              "1993-06-25",
+              "0763-154177",
+              "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap()
         }
-    }
\ No newline at end of file
+    }
@@ -0,0 +1,142 @@
+/**
+Read model of the current balance of every Account, kept up to date by the `BalanceProjector`.
+
+# Example:
+```
+    let balance = get_balance(&get_projection_schema(), &account_id);
+```
+*/
+
+use rql::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::account::AccountEvent;
+use crate::cqrs::money::Money;
+use crate::database::projection_schema::ProjectionSchema;
+use crate::projections::projector::Projector;
+
+/// Current balance of an Account.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Balance {
+  pub aggregate_id: String,
+  pub balance: Money,
+  pub aggregate_version: u32,
+}
+
+/// How much an Account event changes the balance, None for events that do not move money.
+pub fn balance_change(event: &AccountEvent) -> Option<Money> {
+  match event {
+    AccountEvent::Deposit { amount }
+    | AccountEvent::Credit { amount, .. }
+    | AccountEvent::ReverseDebit { amount, .. }
+    | AccountEvent::PayInterest { amount } => Some(*amount),
+    AccountEvent::Withdraw { amount }
+    | AccountEvent::Debit { amount, .. }
+    | AccountEvent::ChargeFee { amount } => amount.checked_neg().ok(),
+    AccountEvent::New { .. }
+    | AccountEvent::FreezeAccount
+    | AccountEvent::UnfreezeAccount
+    | AccountEvent::CloseAccount
+    | AccountEvent::SetOverdraftLimit { .. } => None,
+  }
+}
+
+/// Adds the change to the balance. The account commands only accept amounts in the account currency,
+/// so a mismatch means the event log is corrupt.
+pub fn add_to_balance(balance: Money, change: Money) -> Money {
+  balance.checked_add(change)
+    .unwrap_or_else(|error| panic!("can not add {} to balance {}: {}", change, balance, error))
+}
+
+/// Keeps the `balance` projection table up to date.
+pub struct BalanceProjector;
+
+impl Projector for BalanceProjector {
+  fn name(&self) -> &str {
+    "balance"
+  }
+
+  fn handle(&self, schema: &ProjectionSchema, event: &Event) {
+    // events of other aggregates, and Account events this version does not know, are skipped
+    let domain_event = match event.decode::<AccountEvent>() {
+      Ok(domain_event) => domain_event,
+      Err(_) => return,
+    };
+
+    let mut balance_table = schema.balance_mut();
+    let existing = balance_table
+      .wher(|row| row.aggregate_id == event.aggregate_id)
+      .select(|row| row.id)
+      .next();
+
+    match (existing, domain_event) {
+      (None, AccountEvent::New { currency, .. }) => {
+        balance_table.insert(Balance {
+          aggregate_id: event.aggregate_id.clone(),
+          balance: Money::zero(currency),
+          aggregate_version: event.aggregate_version,
+        });
+      },
+      (Some(id), domain_event) => {
+        let balance = balance_table.get_mut(id).unwrap();
+        // skip events that are already applied, so handling an event twice is harmless
+        if event.aggregate_version > balance.aggregate_version {
+          if let Some(change) = balance_change(&domain_event) {
+            balance.balance = add_to_balance(balance.balance, change);
+          }
+          balance.aggregate_version = event.aggregate_version;
+        }
+      },
+      // the stream does not start with a New event
+      (None, _) => {},
+    }
+  }
+
+  fn reset(&self, schema: &ProjectionSchema) {
+    schema.balance_mut().delete_where(|_| true);
+  }
+}
+
+/// Reads the balance of an Account from the persisted projection.
+#[allow(dead_code)]
+pub fn get_balance(schema: &ProjectionSchema, aggregate_id: &str) -> Option<Money> {
+  schema.balance()
+    .wher(|row| row.aggregate_id == aggregate_id)
+    .select(|row| row.balance)
+    .next()
+}
+
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::projection_schema;
+        use crate::cqrs::money::Currency;
+        use crate::projections::projector;
+        use super::*;
+
+        #[test]
+        #[serial_test::serial]
+        fn projector_keeps_balance_per_account() {
+            let schema = projection_schema::get_projection_schema();
+            schema.balance_mut().delete_where(|_| true);
+            schema.checkpoint_mut().delete_where(|_| true);
+            let store = InMemoryEventStore::new();
+            let open_event = Event::from_domain_event(
+              &AccountEvent::New { account_holder_id: "holder-1".into(), currency: Currency::EUR },
+              HashMap::new(),
+            );
+            let deposit_event = open_event.update_with(&AccountEvent::Deposit { amount: "100.00 EUR".parse().unwrap() }, HashMap::new());
+            let withdraw_event = deposit_event.update_with(&AccountEvent::Withdraw { amount: "25.50 EUR".parse().unwrap() }, HashMap::new());
+            store.append(open_event.clone(), 0).unwrap();
+            store.append(deposit_event.clone(), 1).unwrap();
+            store.append(withdraw_event, 2).unwrap();
+
+            projector::catch_up(&store, &schema, &BalanceProjector);
+            // handling an event twice does not count it twice
+            BalanceProjector.handle(&schema, &deposit_event);
+
+            assert_eq!(get_balance(&schema, &open_event.aggregate_id), Some(Money::new(7450, Currency::EUR)));
+            assert_eq!(get_balance(&schema, "unknown"), None);
+        }
+    }
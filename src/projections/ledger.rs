@@ -0,0 +1,371 @@
+/**
+Double-entry general ledger of the bank, built from the Account events that move money.
+
+Every such event becomes one `Posting` with a debit leg and a credit leg of the same amount, so the
+books always sum to zero. Customer accounts are liabilities of the bank and are named `customer:<account id>`.
+The bank's own accounts are:
+
+- `bank:cash`, the money that was deposited and not yet withdrawn
+- `bank:fee_income`, the fees charged to customers
+- `bank:interest_expense`, the interest paid to customers
+- `bank:transfers_in_transit`, the money of transfers that has left one account but not yet arrived on the other
+
+| Event           | Debit                      | Credit                     |
+|-----------------|----------------------------|----------------------------|
+| deposit         | bank:cash                  | customer                   |
+| withdraw        | customer                   | bank:cash                  |
+| debit           | customer                   | bank:transfers_in_transit  |
+| credit          | bank:transfers_in_transit  | customer                   |
+| reverse_debit   | bank:transfers_in_transit  | customer                   |
+| charge_fee      | customer                   | bank:fee_income            |
+| pay_interest    | bank:interest_expense      | customer                   |
+
+`trial_balance` sums the legs per ledger account, and returns an error if the debits and credits of
+any currency do not add up.
+
+# Example:
+```
+    let schema = get_projection_schema();
+    catch_up(&store, &schema, &LedgerProjector);
+
+    match trial_balance(&schema) {
+        Ok(trial_balance) => println!("{}", trial_balance),
+        Err(error) => panic!("{}", error),
+    }
+```
+*/
+
+use std::fmt;
+use std::collections::BTreeMap;
+use rql::prelude::*;
+use crate::cqrs::account::AccountEvent;
+use crate::cqrs::event::*;
+use crate::cqrs::money::{Currency, Money, MoneyError};
+use crate::database::projection_schema::ProjectionSchema;
+use crate::projections::projector::Projector;
+
+pub static CASH: &str = "bank:cash";
+pub static FEE_INCOME: &str = "bank:fee_income";
+pub static INTEREST_EXPENSE: &str = "bank:interest_expense";
+pub static TRANSFERS_IN_TRANSIT: &str = "bank:transfers_in_transit";
+
+/// Name of the ledger account of a customer's Account.
+pub fn customer_account(account_id: &str) -> String {
+  format!("customer:{}", account_id)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+  Debit,
+  Credit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Leg {
+  pub ledger_account: String,
+  pub side: Side,
+  pub amount: Money,
+}
+
+/// The legs booked for a single event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Posting {
+  /// Position of the event in the event log.
+  pub position: u64,
+  pub event_name: String,
+  pub aggregate_id: String,
+  pub legs: Vec<Leg>,
+}
+
+impl Posting {
+  /// Books the event, or returns None for events that do not move money.
+  pub fn from_event(event: &Event) -> Option<Posting> {
+    let customer = customer_account(&event.aggregate_id);
+
+    let (debit, credit, amount) = match event.decode::<AccountEvent>().ok()? {
+      AccountEvent::Deposit { amount } => (CASH.to_string(), customer, amount),
+      AccountEvent::Withdraw { amount } => (customer, CASH.to_string(), amount),
+      AccountEvent::Debit { amount, .. } => (customer, TRANSFERS_IN_TRANSIT.to_string(), amount),
+      AccountEvent::Credit { amount, .. }
+      | AccountEvent::ReverseDebit { amount, .. } => (TRANSFERS_IN_TRANSIT.to_string(), customer, amount),
+      AccountEvent::ChargeFee { amount } => (customer, FEE_INCOME.to_string(), amount),
+      AccountEvent::PayInterest { amount } => (INTEREST_EXPENSE.to_string(), customer, amount),
+      AccountEvent::New { .. }
+      | AccountEvent::FreezeAccount
+      | AccountEvent::UnfreezeAccount
+      | AccountEvent::CloseAccount
+      | AccountEvent::SetOverdraftLimit { .. } => return None,
+    };
+
+    Some(Posting {
+      position: event.position,
+      event_name: event.event_name.clone(),
+      aggregate_id: event.aggregate_id.clone(),
+      legs: vec![
+        Leg { ledger_account: debit, side: Side::Debit, amount },
+        Leg { ledger_account: credit, side: Side::Credit, amount },
+      ],
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LedgerError {
+  /// The debits and credits in a currency do not sum to the same amount. The books are corrupt.
+  Unbalanced {
+    currency: Currency,
+    debits: Money,
+    credits: Money,
+  },
+  Money(MoneyError),
+}
+
+impl fmt::Display for LedgerError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LedgerError::Unbalanced { currency, debits, credits } => write!(
+        f,
+        "the books do not balance in {}: debits {} and credits {}",
+        currency, debits, credits
+      ),
+      LedgerError::Money(error) => write!(f, "{}", error),
+    }
+  }
+}
+
+impl std::error::Error for LedgerError {}
+
+impl From<MoneyError> for LedgerError {
+  fn from(error: MoneyError) -> LedgerError {
+    LedgerError::Money(error)
+  }
+}
+
+/// Total debits and credits of one ledger account in one currency.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalanceLine {
+  pub ledger_account: String,
+  pub debits: Money,
+  pub credits: Money,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrialBalance {
+  /// Ordered by ledger account, then currency.
+  pub lines: Vec<TrialBalanceLine>,
+}
+
+impl fmt::Display for TrialBalance {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{:<60} {:>22} {:>22}", "account", "debit", "credit")?;
+    for line in &self.lines {
+      writeln!(f, "{:<60} {:>22} {:>22}", line.ledger_account, line.debits.to_string(), line.credits.to_string())?;
+    }
+
+    Ok(())
+  }
+}
+
+/// Sums the legs of all postings per ledger account, and checks that the debits equal the credits in every currency.
+#[allow(dead_code)]
+pub fn trial_balance(schema: &ProjectionSchema) -> Result<TrialBalance, LedgerError> {
+  let postings: Vec<Posting> = schema.ledger().rows().map(|row| row.data.clone()).collect();
+
+  sum_postings(&postings)
+}
+
+fn sum_postings(postings: &[Posting]) -> Result<TrialBalance, LedgerError> {
+  let mut lines: BTreeMap<(String, String), TrialBalanceLine> = BTreeMap::new();
+  let mut totals: BTreeMap<String, (Money, Money)> = BTreeMap::new();
+
+  for leg in postings.iter().flat_map(|posting| &posting.legs) {
+    let currency = leg.amount.currency();
+    let line = lines
+      .entry((leg.ledger_account.clone(), currency.to_string()))
+      .or_insert_with(|| TrialBalanceLine {
+        ledger_account: leg.ledger_account.clone(),
+        debits: Money::zero(currency),
+        credits: Money::zero(currency),
+      });
+    let total = totals
+      .entry(currency.to_string())
+      .or_insert((Money::zero(currency), Money::zero(currency)));
+
+    match leg.side {
+      Side::Debit => {
+        line.debits = line.debits.checked_add(leg.amount)?;
+        total.0 = total.0.checked_add(leg.amount)?;
+      },
+      Side::Credit => {
+        line.credits = line.credits.checked_add(leg.amount)?;
+        total.1 = total.1.checked_add(leg.amount)?;
+      },
+    }
+  }
+
+  for (debits, credits) in totals.into_values() {
+    if debits != credits {
+      return Err(LedgerError::Unbalanced { currency: debits.currency(), debits, credits })
+    }
+  }
+
+  Ok(TrialBalance { lines: lines.into_values().collect() })
+}
+
+/// Keeps the `ledger` projection table up to date.
+pub struct LedgerProjector;
+
+impl Projector for LedgerProjector {
+  fn name(&self) -> &str {
+    "ledger"
+  }
+
+  fn handle(&self, schema: &ProjectionSchema, event: &Event) {
+    let posting = match Posting::from_event(event) {
+      Some(posting) => posting,
+      None => return,
+    };
+
+    let mut ledger_table = schema.ledger_mut();
+    // skip events that are already booked, so handling an event twice is harmless
+    if ledger_table.wher(|row| row.position == event.position).select(|row| row.id).next().is_some() {
+      return
+    }
+
+    ledger_table.insert(posting);
+  }
+
+  fn reset(&self, schema: &ProjectionSchema) {
+    schema.ledger_mut().delete_where(|_| true);
+  }
+}
+
+/// Reads the postings of a customer's Account from the persisted projection, in the order they were booked.
+#[allow(dead_code)]
+pub fn get_postings_by_account(schema: &ProjectionSchema, account_id: &str) -> Vec<Posting> {
+  let mut postings: Vec<Posting> = schema.ledger()
+    .wher(|row| row.aggregate_id == account_id)
+    .select(|row| row.data.clone())
+    .collect();
+  postings.sort_by_key(|posting| posting.position);
+
+  postings
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::collections::HashMap;
+        use crate::cqrs::account::{charge_fee, deposit, open_account, pay_interest, withdraw};
+        use crate::cqrs::account_holder::create_new_account_holder;
+        use crate::cqrs::transaction::transfer;
+        use crate::database::event_store::EventStore;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use crate::database::projection_schema;
+        use crate::projections::projector;
+        use super::*;
+
+        #[test]
+        fn books_deposit_as_cash_against_customer() {
+            let open_event = Event::from_domain_event(
+              &AccountEvent::New { account_holder_id: "holder-1".into(), currency: Currency::SEK },
+              HashMap::new(),
+            );
+            let deposit_event = open_event.update_with(&AccountEvent::Deposit { amount: sek(10000) }, HashMap::new());
+
+            let posting = Posting::from_event(&deposit_event).unwrap();
+
+            assert_eq!(Posting::from_event(&open_event), None);
+            assert_eq!(posting.legs, vec![
+              Leg { ledger_account: CASH.into(), side: Side::Debit, amount: sek(10000) },
+              Leg { ledger_account: customer_account(&open_event.aggregate_id), side: Side::Credit, amount: sek(10000) },
+            ]);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn books_always_balance() {
+            let schema = setup_projection_schema();
+            let store = InMemoryEventStore::new();
+            let from_account_id = store_new_account(&store);
+            let to_account_id = store_new_account(&store);
+            deposit(&store, &from_account_id, sek(10000)).unwrap();
+            withdraw(&store, &from_account_id, sek(1000)).unwrap();
+            transfer(&store, &from_account_id, &to_account_id, sek(2500)).unwrap();
+            charge_fee(&store, &to_account_id, sek(500)).unwrap();
+            pay_interest(&store, &from_account_id, sek(75)).unwrap();
+
+            projector::catch_up(&store, &schema, &LedgerProjector);
+            // handling an event twice does not book it twice
+            LedgerProjector.handle(&schema, &store.last_event(&from_account_id, "Account").unwrap());
+
+            let trial_balance = trial_balance(&schema).unwrap();
+            let line = |ledger_account: &str| trial_balance.lines.iter()
+              .find(|line| line.ledger_account == ledger_account)
+              .cloned()
+              .unwrap();
+            assert_eq!(line(CASH), TrialBalanceLine { ledger_account: CASH.into(), debits: sek(10000), credits: sek(1000) });
+            assert_eq!(line(TRANSFERS_IN_TRANSIT).debits, line(TRANSFERS_IN_TRANSIT).credits);
+            assert_eq!(line(FEE_INCOME).credits, sek(500));
+            assert_eq!(line(INTEREST_EXPENSE).debits, sek(75));
+            assert_eq!(line(&customer_account(&to_account_id)).credits, sek(2500));
+            assert_eq!(get_postings_by_account(&schema, &from_account_id).len(), 4);
+        }
+
+        #[test]
+        fn unbalanced_books_fail() {
+            let broken = Posting {
+              position: 1,
+              event_name: "deposit".into(),
+              aggregate_id: "account".into(),
+              legs: vec![Leg { ledger_account: CASH.into(), side: Side::Debit, amount: sek(100) }],
+            };
+
+            assert_eq!(sum_postings(&[broken]), Err(LedgerError::Unbalanced {
+              currency: Currency::SEK,
+              debits: sek(100),
+              credits: sek(0),
+            }));
+        }
+
+        #[test]
+        fn currencies_balance_separately() {
+            let posting = |amount: Money| Posting {
+              position: 1,
+              event_name: "deposit".into(),
+              aggregate_id: "account".into(),
+              legs: vec![
+                Leg { ledger_account: CASH.into(), side: Side::Debit, amount },
+                Leg { ledger_account: customer_account("account"), side: Side::Credit, amount },
+              ],
+            };
+
+            let trial_balance = sum_postings(&[posting(sek(100)), posting(Money::new(100, Currency::EUR))]).unwrap();
+
+            assert_eq!(trial_balance.lines.len(), 4);
+        }
+
+        fn sek(minor_units: i64) -> Money {
+            Money::new(minor_units, Currency::SEK)
+        }
+
+        fn setup_projection_schema() -> ProjectionSchema {
+            let schema = projection_schema::get_projection_schema();
+            schema.ledger_mut().delete_where(|_| true);
+            schema.checkpoint_mut().delete_where(|_| true);
+            schema
+        }
+
+        fn store_new_account(store: &dyn EventStore) -> String {
+            let account_holder = create_new_account_holder(
+              "Isak Törnros",
+              "19930625-7255",
+              "1993-06-25",
+              "0763-154177",
+              "Nöbbelövs Torg 37, 22652 LUND, Sweden",
+            ).unwrap();
+            store.append(account_holder.clone(), 0).unwrap();
+
+            open_account(store, &account_holder.aggregate_id, Currency::SEK).unwrap().aggregate_id
+        }
+    }
@@ -1 +1,6 @@
-pub mod account_holder;
\ No newline at end of file
+pub mod account;
+pub mod account_holder;
+pub mod balance;
+pub mod ledger;
+pub mod projector;
+pub mod transfer;
\ No newline at end of file
@@ -0,0 +1,280 @@
+/**
+Keeps the read models in the `ProjectionSchema` up to date with the event log.
+
+A projector handles events one at a time and writes the result to its projection tables.
+The runner remembers, per projector, the position of the last event it has handled in the `checkpoint` table.
+On startup every projector only catches up on the events appended since its checkpoint,
+instead of replaying the whole event log from scratch.
+
+The checkpoint is stored once all events of a catch-up are handled, as every store rewrites the whole checkpoint
+table. Handling the events and storing the checkpoint is not atomic, so after a crash the events since the last
+checkpoint are handled again. Projectors must therefore be idempotent, e.g. by ignoring events with an
+aggregate_version they have already seen.
+
+When the way a projection is computed changes, `replay` throws the projection away and rebuilds it
+from the whole event log. The binary exposes this as `rusty-bank replay <projection name>`.
+
+# Example:
+```
+    let store = RqlEventStore::open_default()?;
+    let schema = get_projection_schema();
+
+    catch_up_all(&store, &schema, &all_projectors());
+
+    // rebuild a single projection from scratch
+    let report = replay_by_name(&store, &schema, "account_holder").unwrap();
+```
+*/
+
+use std::fmt;
+use std::time::{Duration, Instant};
+use rql::prelude::*;
+use crate::cqrs::event::*;
+use crate::database::event_store::EventStore;
+use crate::database::projection_schema::ProjectionSchema;
+use crate::projections::account::AccountProjector;
+use crate::projections::account_holder::AccountHolderProjector;
+use crate::projections::balance::BalanceProjector;
+use crate::projections::ledger::LedgerProjector;
+
+pub trait Projector {
+    /// Unique name of the projection, used as key of its checkpoint.
+    fn name(&self) -> &str;
+
+    /// Updates the projection with a single event. Events the projector does not care about are ignored.
+    fn handle(&self, schema: &ProjectionSchema, event: &Event);
+
+    /// Deletes everything the projector has written to its projection tables.
+    fn reset(&self, schema: &ProjectionSchema);
+}
+
+/// Position of the last event a projector has handled.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub projector: String,
+    pub position: u64,
+}
+
+/// Outcome of a replay, printed by the `replay` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayReport {
+    pub projector: String,
+    pub events: usize,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "replayed {} events into projection {} in {:?}", self.events, self.projector, self.elapsed)
+    }
+}
+
+/// All projectors that make up the read side of the bank.
+#[allow(dead_code)]
+pub fn all_projectors() -> Vec<Box<dyn Projector>> {
+    vec![
+        Box::new(AccountHolderProjector),
+        Box::new(AccountProjector),
+        Box::new(BalanceProjector),
+        Box::new(LedgerProjector),
+    ]
+}
+
+/// Returns the position of the last event the projector has handled, or 0 if it has not handled any.
+pub fn get_checkpoint(schema: &ProjectionSchema, projector_name: &str) -> u64 {
+    schema.checkpoint()
+        .wher(|row| row.projector == projector_name)
+        .select(|row| row.position)
+        .next()
+        .unwrap_or(0)
+}
+
+pub fn save_checkpoint(schema: &ProjectionSchema, projector_name: &str, position: u64) {
+    let mut checkpoint_table = schema.checkpoint_mut();
+
+    let existing = checkpoint_table
+        .wher(|row| row.projector == projector_name)
+        .select(|row| row.id)
+        .next();
+
+    match existing {
+        Some(id) => {
+            checkpoint_table.get_mut(id).unwrap().position = position;
+        },
+        None => {
+            checkpoint_table.insert(Checkpoint {
+                projector: projector_name.into(),
+                position,
+            });
+        }
+    }
+}
+
+/// Hands every event after the projector's checkpoint to the projector, in order, and moves the checkpoint along.
+/// Returns the number of events handled.
+pub fn catch_up(store: &dyn EventStore, schema: &ProjectionSchema, projector: &dyn Projector) -> usize {
+    let checkpoint = get_checkpoint(schema, projector.name());
+    let events = store.read_all(checkpoint + 1);
+
+    for event in &events {
+        projector.handle(schema, event);
+    }
+    if let Some(last_event) = events.last() {
+        save_checkpoint(schema, projector.name(), last_event.position);
+    }
+
+    events.len()
+}
+
+/// Catches up every projector. Returns the name of each projector with the number of events it handled.
+#[allow(dead_code)]
+pub fn catch_up_all(store: &dyn EventStore, schema: &ProjectionSchema, projectors: &[Box<dyn Projector>]) -> Vec<(String, usize)> {
+    projectors
+        .iter()
+        .map(|projector| (projector.name().to_string(), catch_up(store, schema, projector.as_ref())))
+        .collect()
+}
+
+/// Wipes the projection and its checkpoint, then replays every event in the store through the projector, in order.
+pub fn replay(store: &dyn EventStore, schema: &ProjectionSchema, projector: &dyn Projector) -> ReplayReport {
+    let started = Instant::now();
+
+    projector.reset(schema);
+    save_checkpoint(schema, projector.name(), 0);
+    let events = catch_up(store, schema, projector);
+
+    ReplayReport {
+        projector: projector.name().into(),
+        events,
+        elapsed: started.elapsed(),
+    }
+}
+
+/// Replays the projection with the given name, or returns None if there is no projector with that name.
+#[allow(dead_code)]
+pub fn replay_by_name(store: &dyn EventStore, schema: &ProjectionSchema, projector_name: &str) -> Option<ReplayReport> {
+    let projector = all_projectors()
+        .into_iter()
+        .find(|projector| projector.name() == projector_name)?;
+
+    Some(replay(store, schema, projector.as_ref()))
+}
+
+// cargo test -- --nocapture
+#[cfg(test)]
+    mod tests {
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use crate::database::in_memory_event_store::InMemoryEventStore;
+        use super::*;
+
+        // remembers the positions of the events it was handed
+        struct RecordingProjector {
+            handled: RefCell<Vec<u64>>,
+        }
+
+        impl Projector for RecordingProjector {
+            fn name(&self) -> &str {
+                "recording"
+            }
+
+            fn handle(&self, _schema: &ProjectionSchema, event: &Event) {
+                self.handled.borrow_mut().push(event.position);
+            }
+
+            fn reset(&self, _schema: &ProjectionSchema) {
+                self.handled.borrow_mut().clear();
+            }
+        }
+
+        fn setup() -> ProjectionSchema {
+            let schema = crate::database::projection_schema::get_projection_schema();
+            schema.checkpoint_mut().delete_where(|_| true);
+            schema
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn catches_up_from_checkpoint_only() {
+            let schema = setup();
+            let store = InMemoryEventStore::new();
+            let projector = RecordingProjector { handled: RefCell::new(vec![]) };
+            store.append(new_event(), 0).unwrap();
+            store.append(new_event(), 0).unwrap();
+
+            let first_run = catch_up(&store, &schema, &projector);
+
+            store.append(new_event(), 0).unwrap();
+            let second_run = catch_up(&store, &schema, &projector);
+            let third_run = catch_up(&store, &schema, &projector);
+
+            assert_eq!(first_run, 2);
+            assert_eq!(second_run, 1);
+            assert_eq!(third_run, 0);
+            assert_eq!(*projector.handled.borrow(), vec![1, 2, 3]);
+            assert_eq!(get_checkpoint(&schema, "recording"), 3);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn catches_up_all_projectors() {
+            let schema = setup();
+            let store = InMemoryEventStore::new();
+            store.append(new_event(), 0).unwrap();
+            save_checkpoint(&schema, "recording", 1);
+            store.append(new_event(), 0).unwrap();
+            let projectors: Vec<Box<dyn Projector>> = vec![Box::new(RecordingProjector { handled: RefCell::new(vec![]) })];
+
+            let handled = catch_up_all(&store, &schema, &projectors);
+
+            assert_eq!(handled, [("recording".to_string(), 1)]);
+            assert_eq!(get_checkpoint(&schema, "recording"), 2);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn checkpoints_are_kept_per_projector() {
+            let schema = setup();
+
+            save_checkpoint(&schema, "a", 3);
+            save_checkpoint(&schema, "b", 5);
+            save_checkpoint(&schema, "a", 4);
+
+            assert_eq!(get_checkpoint(&schema, "a"), 4);
+            assert_eq!(get_checkpoint(&schema, "b"), 5);
+            assert_eq!(get_checkpoint(&schema, "c"), 0);
+            assert_eq!(schema.checkpoint().rows().count(), 2);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn replay_wipes_projection_and_handles_all_events_again() {
+            let schema = setup();
+            let store = InMemoryEventStore::new();
+            let projector = RecordingProjector { handled: RefCell::new(vec![]) };
+            store.append(new_event(), 0).unwrap();
+            store.append(new_event(), 0).unwrap();
+            catch_up(&store, &schema, &projector);
+            store.append(new_event(), 0).unwrap();
+
+            let report = replay(&store, &schema, &projector);
+
+            assert_eq!(report.projector, "recording");
+            assert_eq!(report.events, 3);
+            assert_eq!(*projector.handled.borrow(), vec![1, 2, 3]);
+            assert_eq!(get_checkpoint(&schema, "recording"), 3);
+        }
+
+        #[test]
+        #[serial_test::serial]
+        fn replay_by_unknown_name_does_nothing() {
+            let schema = setup();
+            let store = InMemoryEventStore::new();
+
+            assert_eq!(replay_by_name(&store, &schema, "no_such_projection"), None);
+        }
+
+        fn new_event() -> Event {
+            Event::new(HashMap::new(), HashMap::new(), "AggregateType".into())
+        }
+    }
@@ -0,0 +1,87 @@
+/**
+State of a Transfer between two Accounts, built by folding the events of the transfer stream in order.
+
+Used by the process manager in `cqrs::transaction` to find out how far a transfer has come.
+
+# Example:
+```
+    let events = store.read_stream(&transfer_id, "Transfer");
+    let transfer = Transfer::from_events(&events);
+```
+*/
+
+use rql::prelude::*;
+use crate::cqrs::event::*;
+use crate::cqrs::money::Money;
+use crate::cqrs::transaction::TransferEvent;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum TransferStatus {
+  #[default]
+  Initiated,
+  /// The money has left the source account, and is in transit.
+  Debited,
+  /// The money has arrived on the destination account.
+  Credited,
+  Completed,
+  /// The transfer was given up. If the source account had been debited, the debit has been reversed.
+  Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Transfer {
+  pub aggregate_id: String,
+  pub from_account_id: String,
+  pub to_account_id: String,
+  pub amount: Money,
+  pub status: TransferStatus,
+  /// Why the transfer failed, empty unless the status is Failed.
+  pub failure_reason: String,
+  pub aggregate_version: u32,
+}
+
+impl Transfer {
+  /// Builds the state from the events of one transfer, ordered by aggregate_version.
+  /// Returns None if there are no events.
+  pub fn from_events(events: &[Event]) -> Option<Transfer> {
+    let first = events.first()?;
+    let mut transfer = Transfer {
+      aggregate_id: first.aggregate_id.clone(),
+      ..Default::default()
+    };
+
+    for event in events {
+      transfer.apply(event);
+    }
+
+    Some(transfer)
+  }
+
+  /// Applies a single event on top of the current state.
+  pub fn apply(&mut self, event: &Event) {
+    if let Ok(domain_event) = event.decode::<TransferEvent>() {
+      match domain_event {
+        TransferEvent::TransferInitiated { from_account_id, to_account_id, amount } => {
+          self.from_account_id = from_account_id;
+          self.to_account_id = to_account_id;
+          self.amount = amount;
+          self.status = TransferStatus::Initiated;
+        },
+        TransferEvent::Debited => self.status = TransferStatus::Debited,
+        TransferEvent::Credited => self.status = TransferStatus::Credited,
+        TransferEvent::TransferCompleted => self.status = TransferStatus::Completed,
+        TransferEvent::TransferFailed { reason } => {
+          self.status = TransferStatus::Failed;
+          self.failure_reason = reason;
+        },
+      }
+    }
+
+    self.aggregate_version = event.aggregate_version;
+  }
+
+  /// Completed and failed transfers need no more work.
+  pub fn is_finished(&self) -> bool {
+    matches!(self.status, TransferStatus::Completed | TransferStatus::Failed)
+  }
+}